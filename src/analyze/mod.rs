@@ -0,0 +1,286 @@
+/*
+ * Copyright 2022 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::f64::consts::PI;
+use std::sync::{Arc, Mutex};
+
+use gstreamer::prelude::*;
+
+// Audio is decoded to mono at this rate before analysis.
+const RATE: usize = 22050;
+const FRAME: usize = 1024;
+const HOP: usize = 512;
+
+// Onsets must stand this far above the local mean of the normalised flux.
+const ONSET_DELTA: f64 = 0.07;
+// Frames either side used for the local mean and the local maximum.
+const MEAN_FRAMES: usize = 8;
+const PEAK_FRAMES: usize = 3;
+// Onsets closer together than this are merged.
+const MIN_GAP_SECONDS: f64 = 0.1;
+
+const MIN_BPM: f64 = 60.0;
+const MAX_BPM: f64 = 180.0;
+
+pub fn run(args: &[String]) -> i32 {
+    let mut path = None;
+    let mut pipe = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--pipe" => match iter.next() {
+                Some(p) => pipe = Some(p.clone()),
+                None => {
+                    println!("--pipe expects the name of the reference pipeline");
+                    return 1;
+                }
+            },
+            p => path = Some(p.to_string()),
+        }
+    }
+    let path = match path {
+        Some(p) => p,
+        None => {
+            println!("Usage: live-mix analyze AUDIO_PATH [--pipe PIPELINE]");
+            return 1;
+        }
+    };
+
+    let samples = match decode(path.as_str()) {
+        Ok(s) => s,
+        Err(err) => {
+            println!("Error! {}", err);
+            return 1;
+        }
+    };
+    let flux = spectral_flux(&samples);
+    let onsets = pick_onsets(&flux);
+
+    match tempo(&flux) {
+        Some((bpm, first_beat)) => println!("tempo {:.2} {:.3}", bpm, first_beat),
+        None => println!("// tempo unknown"),
+    }
+    for (time, strength) in onsets {
+        match &pipe {
+            Some(p) => {
+                // a mark is a valid placeholder, and other triggers can hang off it
+                println!("// strength {:.2}", strength);
+                println!("on progress {} {:.3} act mark onset", p, time);
+            },
+            None => println!("{:.3} {:.2}", time, strength),
+        }
+    }
+    0
+}
+
+// Decodes the file to mono float samples at RATE.
+fn decode(path: &str) -> Result<Vec<f32>, String> {
+    let launch = format!(
+        "filesrc name=src ! decodebin ! audioconvert ! audioresample \
+        ! audio/x-raw,format=F32LE,channels=1,rate={} \
+        ! fakesink name=sink signal-handoffs=true sync=false", RATE);
+    let pipeline = match gstreamer::parse_launch(launch.as_str()) {
+        Ok(p) => p.dynamic_cast::<gstreamer::Pipeline>().unwrap(),
+        Err(err) => return Err(format!("Could not create decoder: {}", err)),
+    };
+    let src = pipeline.by_name("src").unwrap();
+    src.set_property("location", path);
+
+    let samples = Arc::new(Mutex::new(Vec::new()));
+    let sink = pipeline.by_name("sink").unwrap();
+    let collect = samples.clone();
+    sink.connect("handoff", false, move |values| {
+        if let Ok(buffer) = values[1].get::<gstreamer::Buffer>() {
+            if let Ok(map) = buffer.map_readable() {
+                let mut samples = collect.lock().unwrap();
+                for bytes in map.as_slice().chunks_exact(4) {
+                    samples.push(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+                }
+            }
+        }
+        None
+    });
+
+    if let Err(err) = pipeline.set_state(gstreamer::State::Playing) {
+        return Err(format!("Could not start decoder: {:?}", err));
+    }
+    let bus = pipeline.bus().unwrap();
+    let mut result = Ok(());
+    for msg in bus.iter_timed(gstreamer::ClockTime::NONE) {
+        match msg.view() {
+            gstreamer::MessageView::Eos(_) => break,
+            gstreamer::MessageView::Error(err) => {
+                result = Err(format!("Could not decode {}: {} ({})", path, err.error(), err.debug().unwrap_or_default()));
+                break;
+            },
+            _ => (),
+        }
+    }
+    let _ = pipeline.set_state(gstreamer::State::Null);
+    result?;
+    let samples = samples.lock().unwrap().clone();
+    Ok(samples)
+}
+
+// In-place radix-2 FFT over (real, imaginary) pairs. The length must be a
+// power of two.
+fn fft(data: &mut [(f64, f64)]) {
+    let n = data.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (wr, wi) = ((angle * k as f64).cos(), (angle * k as f64).sin());
+                let (ar, ai) = data[start + k];
+                let (br, bi) = data[start + k + len / 2];
+                let (tr, ti) = (br * wr - bi * wi, br * wi + bi * wr);
+                data[start + k] = (ar + tr, ai + ti);
+                data[start + k + len / 2] = (ar - tr, ai - ti);
+            }
+        }
+        len <<= 1;
+    }
+}
+
+// Onset detection function: the summed increase in log magnitude per bin
+// between consecutive frames, normalised to a peak of 1.
+fn spectral_flux(samples: &[f32]) -> Vec<f64> {
+    let window = (0..FRAME)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f64 / FRAME as f64).cos())
+        .collect::<Vec<f64>>();
+    let mut previous = vec![0.0; FRAME / 2];
+    let mut flux = Vec::new();
+    let mut start = 0;
+    while start + FRAME <= samples.len() {
+        let mut frame = samples[start..start + FRAME].iter()
+            .zip(window.iter())
+            .map(|(s, w)| (*s as f64 * w, 0.0))
+            .collect::<Vec<(f64, f64)>>();
+        fft(&mut frame);
+        let mut sum = 0.0;
+        for (bin, prev) in frame[..FRAME / 2].iter().zip(previous.iter_mut()) {
+            let magnitude = (1.0 + 100.0 * bin.0.hypot(bin.1)).ln();
+            sum += (magnitude - *prev).max(0.0);
+            *prev = magnitude;
+        }
+        flux.push(sum);
+        start += HOP;
+    }
+    let max = flux.iter().cloned().fold(0.0, f64::max);
+    if max > 0.0 {
+        for f in flux.iter_mut() {
+            *f /= max;
+        }
+    }
+    flux
+}
+
+fn frame_time(frame: f64) -> f64 {
+    (frame * HOP as f64 + FRAME as f64 / 2.0) / RATE as f64
+}
+
+// Picks local maxima of the flux that clear an adaptive threshold, as
+// (seconds, strength) pairs.
+fn pick_onsets(flux: &[f64]) -> Vec<(f64, f64)> {
+    let mut onsets: Vec<(f64, f64)> = Vec::new();
+    for i in 0..flux.len() {
+        let low = i.saturating_sub(MEAN_FRAMES);
+        let high = (i + MEAN_FRAMES + 1).min(flux.len());
+        let mean = flux[low..high].iter().sum::<f64>() / (high - low) as f64;
+        if flux[i] < mean + ONSET_DELTA {
+            continue;
+        }
+        let low = i.saturating_sub(PEAK_FRAMES);
+        let high = (i + PEAK_FRAMES + 1).min(flux.len());
+        if flux[low..high].iter().any(|f| *f > flux[i]) {
+            continue;
+        }
+        let time = frame_time(i as f64);
+        if let Some((last, _)) = onsets.last() {
+            if time - last < MIN_GAP_SECONDS {
+                continue;
+            }
+        }
+        onsets.push((time, flux[i]));
+    }
+    onsets
+}
+
+// Estimates (bpm, first beat in seconds) from the autocorrelation of the
+// flux, preferring tempos near 120 bpm to avoid octave errors.
+fn tempo(flux: &[f64]) -> Option<(f64, f64)> {
+    let frames_per_second = RATE as f64 / HOP as f64;
+    let min_lag = (60.0 / MAX_BPM * frames_per_second).floor() as usize;
+    let max_lag = (60.0 / MIN_BPM * frames_per_second).ceil() as usize;
+    if flux.len() <= max_lag * 2 {
+        return None;
+    }
+    let mean = flux.iter().sum::<f64>() / flux.len() as f64;
+    let centred = flux.iter().map(|f| f - mean).collect::<Vec<f64>>();
+    let correlation = (0..=max_lag + 1)
+        .map(|lag| centred.iter().zip(centred[lag..].iter()).map(|(a, b)| a * b).sum::<f64>())
+        .collect::<Vec<f64>>();
+
+    let mut best_lag = 0;
+    let mut best_score = f64::NEG_INFINITY;
+    for lag in min_lag..=max_lag {
+        let bpm = 60.0 * frames_per_second / lag as f64;
+        let weight = (-0.5 * (bpm / 120.0).log2().powi(2)).exp();
+        let score = correlation[lag] * weight;
+        if score > best_score {
+            best_score = score;
+            best_lag = lag;
+        }
+    }
+    if best_score <= 0.0 {
+        return None;
+    }
+
+    // parabolic interpolation between neighbouring lags
+    let (a, b, c) = (correlation[best_lag - 1], correlation[best_lag], correlation[best_lag + 1]);
+    let denominator = a - 2.0 * b + c;
+    let offset = if denominator != 0.0 { 0.5 * (a - c) / denominator } else { 0.0 };
+    let lag = best_lag as f64 + offset.clamp(-0.5, 0.5);
+
+    let mut best_phase = 0;
+    let mut best_sum = f64::NEG_INFINITY;
+    for phase in 0..lag.ceil() as usize {
+        let mut sum = 0.0;
+        let mut beat = phase as f64;
+        while (beat.round() as usize) < flux.len() {
+            sum += flux[beat.round() as usize];
+            beat += lag;
+        }
+        if sum > best_sum {
+            best_sum = sum;
+            best_phase = phase;
+        }
+    }
+    Some((60.0 * frames_per_second / lag, frame_time(best_phase as f64)))
+}
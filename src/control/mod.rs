@@ -0,0 +1,95 @@
+/*
+ * Copyright 2022 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::thread;
+
+use crossbeam_channel::{Receiver, Sender};
+
+// A line from a control client, with a channel for the one line reply.
+pub struct ControlRequest {
+    pub line: String,
+    pub reply: Sender<String>,
+}
+
+// Listens on a Unix socket at the path, passing each line clients send to
+// the returned channel and writing back the reply.
+pub fn listen(path: &str) -> Result<Receiver<ControlRequest>, String> {
+    // a socket left behind by an earlier run
+    if let Ok(meta) = fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            return Err(format!("{} exists and is not a socket", path));
+        }
+        if let Err(err) = fs::remove_file(path) {
+            return Err(format!("Could not remove old socket {}: {}", path, err));
+        }
+    }
+    let listener = match UnixListener::bind(path) {
+        Ok(l) => l,
+        Err(err) => return Err(format!("Could not listen on {}: {}", path, err)),
+    };
+    println!("Listening for control commands on {}", path);
+    let (tx, rx) = crossbeam_channel::unbounded();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let tx = tx.clone();
+                    thread::spawn(move || serve(stream, tx));
+                },
+                Err(err) => println!("Control connection failed: {}", err),
+            }
+        }
+    });
+    Ok(rx)
+}
+
+fn serve(stream: UnixStream, requests: Sender<ControlRequest>) {
+    let mut writer = match stream.try_clone() {
+        Ok(w) => w,
+        Err(err) => {
+            println!("Control connection failed: {}", err);
+            return;
+        }
+    };
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(_) => return,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match ask(&requests, line) {
+            Some(r) => r,
+            None => return,
+        };
+        if writeln!(writer, "{}", reply).is_err() {
+            return;
+        }
+    }
+}
+
+// Passes the line to the gtk thread and waits for the reply, or None when the
+// show has stopped.
+pub fn ask(requests: &Sender<ControlRequest>, line: String) -> Option<String> {
+    let (tx, rx) = crossbeam_channel::bounded(1);
+    requests.send(ControlRequest { line, reply: tx }).ok()?;
+    Some(rx.recv().unwrap_or_else(|_| "error no reply".to_string()))
+}
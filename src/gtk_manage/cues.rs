@@ -0,0 +1,125 @@
+/*
+ * Copyright 2022 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::time::{Duration, Instant};
+
+use gtk::traits::LabelExt;
+use serde_json::json;
+
+use crate::http;
+use crate::script::{Follow, Pattern};
+use crate::script::action::fire;
+
+// Where the operator is in the pattern's cue stack. GO runs the cue on
+// standby and moves the standby on by one, like a theatre desk.
+pub struct CueStack {
+    standby: usize,
+    // the cue GO ran last
+    current: Option<usize>,
+    // when the cue on standby runs by itself, counted from the GO of an
+    // autofollow cue
+    follow_at: Option<Instant>,
+    // the control window's display
+    label: Option<gtk::Label>,
+}
+
+impl CueStack {
+    pub fn new(pattern: &Pattern, label: Option<gtk::Label>) -> CueStack {
+        let cues = CueStack {
+            standby: 0,
+            current: None,
+            follow_at: None,
+            label,
+        };
+        if !pattern.cues.is_empty() {
+            cues.show(pattern);
+        }
+        cues
+    }
+
+    pub fn go(&mut self, pattern: &Pattern) {
+        self.follow_at = None;
+        if self.standby >= pattern.cues.len() {
+            println!("No cue on standby");
+            return;
+        }
+        // autocontinue runs the following cues in the same GO
+        while let Some(cue) = pattern.cues.get(self.standby) {
+            fire(format!("cue {}", cue.name).as_str(), &cue.events);
+            self.current = Some(self.standby);
+            self.standby += 1;
+            match cue.follow {
+                Follow::Continue => continue,
+                Follow::After(seconds) => self.follow_at = Instant::now().checked_add(Duration::from_secs_f64(seconds)),
+                Follow::Manual => (),
+            }
+            break;
+        }
+        self.show(pattern);
+    }
+
+    // Puts the previous cue on standby, so GO runs the last cue again.
+    pub fn back(&mut self, pattern: &Pattern) {
+        self.follow_at = None;
+        self.standby = self.standby.saturating_sub(1);
+        self.show(pattern);
+    }
+
+    // Puts the named cue on standby without running anything.
+    pub fn jump(&mut self, pattern: &Pattern, name: &str) -> Result<(), String> {
+        let index = match pattern.cues.iter().position(|c| c.name == name) {
+            Some(i) => i,
+            None => return Err(format!("Unknown cue: {}", name)),
+        };
+        self.follow_at = None;
+        self.standby = index;
+        self.show(pattern);
+        Ok(())
+    }
+
+    pub fn poll(&mut self, pattern: &Pattern) {
+        if self.follow_at.map(|t| Instant::now() >= t).unwrap_or(false) {
+            self.go(pattern);
+        }
+    }
+
+    // Keeps the standby in range when a reload shortens the stack.
+    pub fn reload(&mut self, pattern: &Pattern) {
+        let len = pattern.cues.len();
+        self.standby = self.standby.min(len);
+        self.current = self.current.filter(|i| *i < len);
+        if self.standby >= len {
+            self.follow_at = None;
+        }
+        self.show(pattern);
+    }
+
+    // The names of the current and standby cues.
+    pub fn describe<'a>(&self, pattern: &'a Pattern) -> (Option<&'a str>, Option<&'a str>) {
+        let name = |i: usize| pattern.cues.get(i).map(|c| c.name.as_str());
+        (self.current.and_then(name), name(self.standby))
+    }
+
+    fn show(&self, pattern: &Pattern) {
+        let (current, standby) = self.describe(pattern);
+        let text = |n: Option<&str>| n.unwrap_or("-").to_string();
+        println!("Cue: {}, standby: {}", text(current), text(standby));
+        if let Some(label) = &self.label {
+            label.set_text(format!("Cue: {}\nStandby: {}", text(current), text(standby)).as_str());
+        }
+        http::publish(json!({"type": "cue", "current": current, "standby": standby}));
+    }
+}
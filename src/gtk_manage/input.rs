@@ -0,0 +1,148 @@
+/*
+ * Copyright 2022 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use gtk::gdk::{EventMask, EventType, ModifierType};
+use gtk::prelude::WidgetExtManual;
+use gtk::traits::*;
+
+use crate::midi::MidiEvent;
+use crate::osc::OscMessage;
+use crate::script::{MidiKind, Pattern, PointerKind};
+use crate::script::action::fire;
+
+// Runs the pattern's key triggers on key presses in the window.
+pub fn connect_keys(window: &gtk::Window, pattern: Rc<RefCell<Pattern>>) {
+    window.connect_key_press_event(move |_, event| {
+        let key = event.keyval().to_lower();
+        let modifiers = event.state() & (ModifierType::CONTROL_MASK | ModifierType::SHIFT_MASK | ModifierType::MOD1_MASK | ModifierType::SUPER_MASK);
+        let mut handled = false;
+        for trigger in &pattern.borrow().key_events {
+            if trigger.key == key && trigger.modifiers == modifiers {
+                fire(format!("key {}", key.name().map(|n| n.to_string()).unwrap_or_default()).as_str(), &trigger.events);
+                handled = true;
+            }
+        }
+        gtk::Inhibit(handled)
+    });
+}
+
+// Runs the pattern's click and hover triggers for the named window on pointer
+// events in its drawing area.
+pub fn connect_pointer(area: &gtk::DrawingArea, name: &str, pattern: Rc<RefCell<Pattern>>) {
+    area.add_events(EventMask::BUTTON_PRESS_MASK | EventMask::ENTER_NOTIFY_MASK | EventMask::LEAVE_NOTIFY_MASK);
+    let click_name = name.to_string();
+    let click_pattern = pattern.clone();
+    area.connect_button_press_event(move |_, event| {
+        // double clicks also send a second plain press
+        if event.event_type() != EventType::ButtonPress {
+            return gtk::Inhibit(false);
+        }
+        fire_pointer(&click_pattern.borrow(), &click_name, |kind| match kind {
+            PointerKind::Click(button) => button.map(|b| b == event.button()).unwrap_or(true),
+            _ => false,
+        })
+    });
+    let enter_name = name.to_string();
+    let enter_pattern = pattern.clone();
+    area.connect_enter_notify_event(move |_, _| {
+        fire_pointer(&enter_pattern.borrow(), &enter_name, |kind| matches!(kind, PointerKind::Enter))
+    });
+    let leave_name = name.to_string();
+    area.connect_leave_notify_event(move |_, _| {
+        fire_pointer(&pattern.borrow(), &leave_name, |kind| matches!(kind, PointerKind::Leave))
+    });
+}
+
+fn fire_pointer<F>(pattern: &Pattern, window: &str, matches: F) -> gtk::Inhibit
+    where F: Fn(&PointerKind) -> bool,
+{
+    let mut handled = false;
+    for trigger in &pattern.pointer_events {
+        if trigger.window == window && matches(&trigger.kind) {
+            fire(format!("pointer {}", window).as_str(), &trigger.events);
+            handled = true;
+        }
+    }
+    gtk::Inhibit(handled)
+}
+
+// Runs the pattern's signal triggers from the main loop, so their actions run
+// on the gtk thread like channel messages.
+pub fn connect_signals(pattern: Rc<RefCell<Pattern>>) {
+    let signals = pattern.borrow().signal_events.iter().map(|t| t.signal).collect::<HashSet<i32>>();
+    for signal in signals {
+        let pattern = pattern.clone();
+        gtk::glib::unix_signal_add_local(signal, move || {
+            println!("Received signal {}", signal);
+            for trigger in &pattern.borrow().signal_events {
+                if trigger.signal == signal {
+                    fire(format!("signal {}", signal).as_str(), &trigger.events);
+                }
+            }
+            gtk::glib::Continue(true)
+        });
+    }
+}
+
+// Runs the pattern's OSC triggers for the message's address.
+pub fn fire_osc(pattern: &Pattern, message: &OscMessage) {
+    let values = message.args.iter().map(|a| a.to_string()).collect::<Vec<String>>();
+    let mut handled = false;
+    for trigger in &pattern.osc_events {
+        if trigger.address != message.address {
+            continue;
+        }
+        handled = true;
+        fire(format!("osc {}", message.address).as_str(), &trigger.events);
+        if let Some(template) = &trigger.template {
+            if let Err(err) = template.exec(pattern, &values) {
+                println!("Error from OSC {}: {}", message.address, err);
+            }
+        }
+    }
+    if !handled {
+        println!("No trigger for OSC {} {:?}", message.address, values);
+    }
+}
+
+// Runs the pattern's MIDI triggers for the event. Actions taking values get
+// the velocity or controller value as a fraction of 127, then the raw value.
+pub fn fire_midi(pattern: &Pattern, event: &MidiEvent) {
+    for trigger in &pattern.midi_events {
+        let raw = match (&trigger.kind, event) {
+            (MidiKind::Note { channel, note, min_velocity }, MidiEvent::Note { channel: c, note: n, velocity })
+                if channel == c && note == n && velocity >= min_velocity => *velocity as i32,
+            (MidiKind::Control { channel, param }, MidiEvent::Control { channel: c, param: p, value })
+                if channel == c && param == p => *value,
+            _ => continue,
+        };
+        let label = match &trigger.kind {
+            MidiKind::Note { channel, note, .. } => format!("midi note {} {}", channel, note),
+            MidiKind::Control { channel, param } => format!("midi cc {} {}", channel, param),
+        };
+        fire(label.as_str(), &trigger.events);
+        if let Some(template) = &trigger.template {
+            let values = vec![(raw as f64 / 127.0).to_string(), raw.to_string()];
+            if let Err(err) = template.exec(pattern, &values) {
+                println!("Error from MIDI {:?}: {}", event, err);
+            }
+        }
+    }
+}
@@ -0,0 +1,74 @@
+/*
+ * Copyright 2022 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use crate::gtk_manage::monitor::{self, Axis, Coord, Placement};
+use crate::script::GridLayout;
+
+#[derive(Clone, Copy)]
+pub struct Cell {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Cell {
+    // Where a window of the given size sits when centred in the cell.
+    pub fn centre(&self, size: (i32, i32)) -> (i32, i32) {
+        (self.x + (self.width - size.0) / 2, self.y + (self.height - size.1) / 2)
+    }
+}
+
+// Splits the layout's monitor into cells, with the gap between cells and
+// around the edges.
+pub fn grid_cells(layout: &GridLayout) -> Result<Vec<(String, Cell)>, String> {
+    let placement = match &layout.monitor {
+        Some(m) => Placement::monitor(m.as_str())?,
+        None => {
+            let desktop = Placement::desktop();
+            match monitor::display().and_then(|d| d.primary_monitor()) {
+                Some(m) => {
+                    let g = m.geometry();
+                    Placement { x: g.x(), y: g.y(), ..desktop }
+                },
+                None => desktop,
+            }
+        }
+    };
+    let gap = Coord::from_setting(&layout.gap)?;
+    let gap_x = placement.length(Axis::X, gap);
+    let gap_y = placement.length(Axis::Y, gap);
+    let (rows, cols) = (layout.rows as i32, layout.cols as i32);
+    let width = (placement.width - gap_x * (cols + 1)) / cols;
+    let height = (placement.height - gap_y * (rows + 1)) / rows;
+    let mut cells = Vec::new();
+    for row in 0..rows {
+        for col in 0..cols {
+            let cell = format!("r{}c{}", row + 1, col + 1);
+            let cell = match &layout.name {
+                Some(name) => format!("{}.{}", name, cell),
+                None => cell,
+            };
+            cells.push((cell, Cell {
+                x: placement.x + gap_x + col * (width + gap_x),
+                y: placement.y + gap_y + row * (height + gap_y),
+                width,
+                height,
+            }));
+        }
+    }
+    Ok(cells)
+}
@@ -14,53 +14,65 @@
  * limitations under the License.
 */
 
-use std::borrow::Borrow;
-use std::cell::{RefCell, RefMut};
-use std::collections::{HashMap, HashSet};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
 use std::rc::Rc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use gstreamer::prelude::{ElementExtManual, GstBinExt};
-use gstreamer_video::prelude::VideoOverlayExtManual;
-use gtk::prelude::{Cast, ApplicationCommandLineExt, ApplicationExt};
+use gstreamer_video::prelude::{VideoOverlayExt, VideoOverlayExtManual};
+use gtk::gio::{Cancellable, FileMonitorEvent, FileMonitorFlags};
+use gtk::gio::prelude::{FileExt, FileMonitorExt};
+use gtk::prelude::{Cast, ApplicationExt, IsA};
 
 use gtk::traits::*;
-use crate::none_bail;
+use crate::{catch_bail, none_bail};
 
+use crate::gtk_manage::cues::CueStack;
+use crate::gtk_manage::layout::Cell;
+use crate::gtk_manage::monitor::{Axis, Coord, Placement};
+use crate::gtk_manage::motion::MovingPart;
+use crate::gtk_manage::options::Options;
+use crate::gtk_manage::progress::Progress;
+use crate::gtk_manage::reload::Reload;
+use crate::gtk_manage::timers::Timers;
+use crate::gtk_manage::window::{LogicalWindow, OutputWindow, Surface};
+use crate::{control, http, midi, osc, repl};
+use crate::repl::Completions;
 use crate::script::{ParsedSetting, Pattern};
+use crate::script::action::{fire, parse_single_event};
 
+pub mod cues;
+pub mod input;
+pub mod layout;
+pub mod monitor;
+pub mod motion;
+pub mod options;
+pub mod progress;
+mod reload;
+mod scenes;
+pub mod shape;
+pub mod timers;
 pub mod window;
 
-struct MovingPart {
-    pipeline: gstreamer::Pipeline,
-    window: String,
-    start: i64,
-    end: i64,
-    start_x: i32,
-    end_x: i32,
-    start_y: i32,
-    end_y: i32,
-    path_x: String,
-    path_y: String,
+// Everything on screen that window actions can refer to.
+struct Stage {
+    windows: HashMap<String, OutputWindow>,
+    cells: HashMap<String, Cell>,
+    // windows are logical rather than on a display
+    headless: bool,
 }
 
-pub fn run_app(app: &gtk::Application, args: &gtk::gio::ApplicationCommandLine) -> i32 {
+pub fn run_app<A: IsA<gtk::gio::Application>>(app: &A, options: &Options) -> i32 {
     app.hold();
-    let args = args.clone().arguments();
-    if args.len() < 2 {
-        println!("Usage: {} PATTERN_PATH", args[0].clone().into_string().unwrap());
-    }
-    let path = match args[1].clone().into_string() {
-        Ok(p) => p,
-        Err(_) => {
-            println!("Couldn't parse pattern path");
-            app.release();
-            return 1;
-        }
-    };
+    // the main loop keeps its own copy
+    let options = options.clone();
+    let path = options.path.clone();
 
     let p = Pattern::parse_pattern(path);
     let pattern = match p {
-        Ok(p) => p,
+        Ok(p) => Rc::new(RefCell::new(p)),
         Err(err) => {
             println!("Error! {:?}", err);
             app.release();
@@ -68,199 +80,544 @@ pub fn run_app(app: &gtk::Application, args: &gtk::gio::ApplicationCommandLine)
         }
     };
 
-    let mut windows: HashMap<String, (Rc<RefCell<gtk::Window>>, u32)> = HashMap::new();
+    monitor::print_monitors();
+    let mut windows: HashMap<String, OutputWindow> = HashMap::new();
+    let names = pattern.borrow().pipes.keys().cloned().collect::<Vec<String>>();
+    for name in names {
+        match open_output(&pattern, name.as_str(), options.headless) {
+            Ok(Some(output)) => {
+                windows.insert(name, output);
+            },
+            Ok(None) => (),
+            Err(err) => {
+                println!("{}", err);
+                app.release();
+                return 1;
+            }
+        }
+    }
 
-    for (name, elem) in &pattern.pipes {
-        let (elem, tags) = elem;
-        let mut needs_window = false;
-        for (tag, setting) in tags {
-            if tag == "gtktag" {
-                match setting {
-                    ParsedSetting::String(s) => {
-                        if s == "window" {
-                            needs_window = true;
-                            break;
-                        }
-                    },
-                    _ => ()
-                }
+    let mut cells = HashMap::new();
+    for layout in &pattern.borrow().layouts {
+        match layout::grid_cells(layout) {
+            Ok(grid) => cells.extend(grid),
+            Err(err) => {
+                println!("Error laying out grid: {}", err);
+                app.release();
+                return 1;
             }
         }
-        if needs_window {
-            let (mut x, mut y, mut width, mut height) = (0, 0, 0, 0);
-            for (tag, setting) in tags {
-                match tag.as_str() {
-                    "x" => {
-                        match setting {
-                            ParsedSetting::Int(i) => x = *i,
-                            _ => println!("bad window parameter"),
-                        }
-                    }
-                    "y" => {
-                        match setting {
-                            ParsedSetting::Int(i) => y = *i,
-                            _ => println!("bad window parameter"),
-                        }
-                    }
-                    "width" => {
-                        match setting {
-                            ParsedSetting::Int(i) => width = *i,
-                            _ => println!("bad window parameter"),
-                        }
-                    }
-                    "height" => {
-                        match setting {
-                            ParsedSetting::Int(i) => height = *i,
-                            _ => println!("bad window parameter"),
-                        }
-                    }
-                    _ => ()
-                }
+    }
+    let mut stage = Stage { windows, cells, headless: options.headless };
+
+    let label = if options.control_window {
+        let (control, label) = window::create_control_window();
+        input::connect_keys(&control, pattern.clone());
+        Some(label)
+    } else {
+        None
+    };
+
+    input::connect_signals(pattern.clone());
+
+    fire("pre", &pattern.borrow().pre_events);
+
+    // control socket and http requests, answered the same way
+    let mut remotes = Vec::new();
+    if let Some(path) = &options.control {
+        match control::listen(path.as_str()) {
+            Ok(rx) => remotes.push(rx),
+            Err(err) => {
+                println!("{}", err);
+                app.release();
+                return 1;
             }
-            let (window, xid) = window::create_gtk_window(x, y, width, height);
-            window.hide();
-
-            let pipeline = elem.clone().dynamic_cast::<gstreamer::Pipeline>().unwrap();
-            let sink_elem = pipeline.by_name("sink").unwrap();
-            let sink = sink_elem.dynamic_cast::<gstreamer_video::VideoOverlay>().unwrap();
-            unsafe {
-                sink.set_window_handle(xid as usize);
+        }
+    }
+    if let Some(port) = options.http {
+        match http::listen(port) {
+            Ok(rx) => remotes.push(rx),
+            Err(err) => {
+                println!("{}", err);
+                app.release();
+                return 1;
             }
-            windows.insert(name.clone(), (Rc::new(RefCell::new(window)), xid));
         }
     }
 
-    for event in &pattern.pre_events {
-        event.exec();
-    }
+    let osc = match options.osc {
+        Some(port) => match osc::listen(port, options.osc_lan) {
+            Ok(rx) => Some(rx),
+            Err(err) => {
+                println!("{}", err);
+                app.release();
+                return 1;
+            }
+        },
+        None => None,
+    };
 
-    let mut moving_parts: Vec<MovingPart> = Vec::new();
-    let recv = pattern.listen.as_ref().unwrap().clone();
-    let mut nano_map: HashSet<(String, u64)> = HashSet::new();
-    gtk::glib::timeout_add_local(Duration::from_millis(10), move || {
-        while let Ok(msg) = recv.try_recv() {
-            println!("Received message: {}", msg.clone());
-            match handle_message(&pattern, &windows, msg, &mut moving_parts) {
-                Ok(b) => if !b {return gtk::glib::Continue(false)},
-                Err(e) => println!("Error from message: {}", e)
+    let midi = if pattern.borrow().midi_events.is_empty() {
+        None
+    } else {
+        match midi::listen("live-mix") {
+            Ok(rx) => Some(rx),
+            Err(err) => {
+                println!("{}", err);
+                app.release();
+                return 1;
             }
         }
+    };
 
-        for ((name, nanos), events) in &pattern.time_events {
-            if nano_map.contains(&(name.clone(), nanos.clone())) {
-                continue
+    // the watched file's last change and the text that is running
+    let mut watch = if options.watch {
+        let changed = Rc::new(std::cell::Cell::new(None));
+        let file = gtk::gio::File::for_path(&options.path);
+        let monitor = match file.monitor_file(FileMonitorFlags::NONE, Cancellable::NONE) {
+            Ok(m) => m,
+            Err(err) => {
+                println!("Could not watch {}: {}", options.path, err);
+                app.release();
+                return 1;
             }
-            let p = match pattern.pipes.get(name) {
-                Some((p, _)) => p,
-                None => panic!("Could not find node {}", name),
-            };
-            let d = p.clone().query_position_generic(gstreamer::Format::Time);
-            if let Some(d) = d {
-                let current_nanos = d.value();
-                if (current_nanos) > (nanos.clone() as i64) {
-                    nano_map.insert((name.clone(), nanos.clone()));
-                    for event in events {
-                        event.exec();
-                    }
+        };
+        let source = fs::read_to_string(&options.path).unwrap_or_default();
+        let changed_clone = changed.clone();
+        monitor.connect_changed(move |_, _, _, event| {
+            if event == FileMonitorEvent::ChangesDoneHint || event == FileMonitorEvent::Created {
+                changed_clone.set(Some(Instant::now()));
+            }
+        });
+        Some((monitor, changed, source))
+    } else {
+        None
+    };
+
+    let console = if options.repl {
+        let completions = Arc::new(Mutex::new(Completions::new(&pattern.borrow())));
+        Some((repl::start(completions.clone()), completions))
+    } else {
+        None
+    };
+
+    let recv = pattern.borrow().listen.as_ref().unwrap().clone();
+    let mut timers = Timers::new(&pattern.borrow());
+    let mut progress = Progress::new(&pattern.borrow());
+    let mut cues = CueStack::new(&pattern.borrow(), label);
+    // window motion runs on each window's frame clock, see motion::animate
+    gtk::glib::timeout_add_local(Duration::from_millis(10), move || {
+        // a reload swaps in a new pattern and stage, so runs before anything holds them
+        if let Some((_, changed, source)) = &mut watch {
+            // editors often write a file in several steps
+            if changed.get().map(|t| t.elapsed() > Duration::from_millis(200)).unwrap_or(false) {
+                changed.set(None);
+                match fs::read_to_string(&options.path) {
+                    Ok(text) => match reload::reload(&pattern, &mut stage, source, &text) {
+                        Ok(Reload::Applied) => {
+                            println!("Reloaded {}", options.path);
+                            *source = text;
+                            timers.reload(&pattern.borrow());
+                            progress.reload();
+                            cues.reload(&pattern.borrow());
+                            if let Some((_, completions)) = &console {
+                                *completions.lock().unwrap() = Completions::new(&pattern.borrow());
+                            }
+                        },
+                        Ok(Reload::Restart(reason)) => reload::restart(&pattern.borrow(), &reason),
+                        Err(err) => println!("Error reloading {}: {}", options.path, err),
+                    },
+                    Err(err) => println!("Could not read {}: {}", options.path, err),
                 }
             }
         }
 
-        let mut remove_parts: Vec<usize> = Vec::new();
-        for (i, part) in moving_parts.iter().enumerate() {
-            let time = match part.pipeline.query_position_generic(gstreamer::Format::Time) {
-                Some(v) => v.value(),
-                None => continue,
-            };
+        // console lines change the pattern, so come before borrowing it
+        if let Some((console, completions)) = &console {
+            while let Ok(request) = console.try_recv() {
+                let result = handle_console(&pattern, &mut stage, &mut timers, &mut cues, request.line.as_str());
+                *completions.lock().unwrap() = Completions::new(&pattern.borrow());
+                let _ = request.reply.send(reply(result));
+            }
+        }
 
-            let (win, _) = windows.get(&part.window).unwrap();
-            let wrc: Rc<RefCell<gtk::Window>> = win.clone();
-            let window: &RefCell<gtk::Window> = wrc.borrow();
-            let wc = window.borrow_mut();
-            if time < part.start {
-                continue;
+        let pattern = pattern.borrow();
+        while let Ok(msg) = recv.try_recv() {
+            println!("Received message: {}", msg.clone());
+            match handle_message(&pattern, &stage, &mut timers, &mut cues, msg) {
+                Ok(b) => if !b {return gtk::glib::Continue(false)},
+                Err(e) => println!("Error from message: {}", e)
             }
-            if time > part.end {
-                wc.move_(part.end_x, part.end_y);
-                remove_parts.push(i);
-                continue;
+        }
+
+        for remote in &remotes {
+            while let Ok(request) = remote.try_recv() {
+                println!("Control command: {}", request.line);
+                let result = handle_control(&pattern, &stage, &mut timers, &mut cues, request.line.as_str());
+                let _ = request.reply.send(reply(result));
             }
+        }
 
-            let time_frac = (time - part.start) as f64 / (part.end - part.start) as f64;
-            let x_diff = part.end_x - part.start_x;
-            let y_diff = part.end_y - part.start_y;
-            let x_abs = part.start_x + match part.path_x.as_str() {
-                "mcos" => x_diff as f64 * (1.0 - f64::to_radians(time_frac * 90.0).cos()),
-                _ => x_diff as f64,
-            } as i32;
-            let y_abs = part.start_y + match part.path_y.as_str() {
-                "mcos" => y_diff as f64 * (1.0 - f64::to_radians(time_frac * 90.0).cos()),
-                _ => y_diff as f64,
-            } as i32;
-            wc.move_(x_abs, y_abs);
+        if let Some(osc) = &osc {
+            while let Ok(message) = osc.try_recv() {
+                input::fire_osc(&pattern, &message);
+            }
         }
 
-        let mut offset = 0;
-        for i in remove_parts {
-            moving_parts.remove(i - offset);
-            offset = offset + 1;
+        if let Some(midi) = &midi {
+            while let Ok(event) = midi.try_recv() {
+                input::fire_midi(&pattern, &event);
+            }
         }
 
+        timers.poll(&pattern);
+
+        cues.poll(&pattern);
+
+        progress.poll(&pattern);
+
         gtk::glib::Continue(true)
     });
     0
 }
 
-fn handle_message(pattern: &Pattern, windows: &HashMap<String, (Rc<RefCell<gtk::Window>>, u32)>, msg: String, parts: &mut Vec<MovingPart>) -> Result<bool, String> {
+// Creates the window for a pipeline tagged with gtktag window, or None for
+// other pipelines.
+fn open_output(pattern: &Rc<RefCell<Pattern>>, name: &str, headless: bool) -> Result<Option<OutputWindow>, String> {
+    let p = pattern.borrow();
+    let (elem, tags) = none_bail!(p.pipes.get(name), format!("Pipeline not found: {}", name));
+    if !p.has_window(name) {
+        if headless {
+            window::replace_audio_sinks(&elem.clone().dynamic_cast::<gstreamer::Pipeline>().unwrap())?;
+        }
+        return Ok(None);
+    }
+    let mut monitor_name = None;
+    let (mut x, mut y, mut width, mut height) = (Coord::Pixels(0), Coord::Pixels(0), Coord::Pixels(0), Coord::Pixels(0));
+    for (tag, setting) in tags {
+        let coord = match tag.as_str() {
+            "x" => &mut x,
+            "y" => &mut y,
+            "width" => &mut width,
+            "height" => &mut height,
+            "monitor" => {
+                match setting {
+                    ParsedSetting::String(s) => monitor_name = Some(s.clone()),
+                    _ => println!("bad window parameter"),
+                }
+                continue
+            }
+            _ => continue
+        };
+        match Coord::from_setting(setting) {
+            Ok(c) => *coord = c,
+            Err(e) => println!("bad window parameter: {}", e),
+        }
+    }
+    let placement = match monitor_name {
+        Some(m) => match Placement::monitor(m.as_str()) {
+            Ok(p) => p,
+            Err(err) => return Err(format!("Error placing window {}: {}", name, err)),
+        },
+        None => Placement::desktop(),
+    };
+    let (x, y) = (placement.position(Axis::X, x), placement.position(Axis::Y, y));
+    let (width, height) = (placement.length(Axis::X, width), placement.length(Axis::Y, height));
+    let pipeline = elem.clone().dynamic_cast::<gstreamer::Pipeline>().unwrap();
+    if headless {
+        window::replace_sink(&pipeline)?;
+        let window = Surface::Logical(Rc::new(LogicalWindow::new(x, y, width, height)));
+        return Ok(Some(OutputWindow { window, placement, motion: Rc::new(std::cell::Cell::new(0)) }));
+    }
+
+    let (window, area, xid) = window::create_gtk_window(x, y, width, height);
+    window.hide();
+
+    let sink_elem = none_bail!(pipeline.by_name("sink"), format!("Window pipeline {} has no element named sink", name));
+    let sink = catch_bail!(sink_elem.dynamic_cast::<gstreamer_video::VideoOverlay>(), format!("The sink of {} is not a video overlay", name));
+    if p.pointer_events.iter().any(|t| t.window == name) {
+        // only one X client can listen for button presses on a window
+        sink.handle_events(false);
+        input::connect_pointer(&area, name, pattern.clone());
+    }
+    unsafe {
+        sink.set_window_handle(xid as usize);
+    }
+    input::connect_keys(&window, pattern.clone());
+    Ok(Some(OutputWindow { window: Surface::Gtk(window), placement, motion: Rc::new(std::cell::Cell::new(0)) }))
+}
+
+fn handle_message(pattern: &Pattern, stage: &Stage, timers: &mut Timers, cues: &mut CueStack, msg: String) -> Result<bool, String> {
     if msg == " terminate" {
         std::process::exit(0);
     }
-    if msg == "pre" {
-        for event in &pattern.pre_events {
-            event.exec();
+    if let Some(name) = msg.strip_prefix(" mark ") {
+        timers.mark(pattern, name);
+        return Ok(true);
+    }
+    if let Some(command) = msg.strip_prefix(" cue ") {
+        match command.split_whitespace().collect::<Vec<&str>>()[..] {
+            ["go"] => cues.go(pattern),
+            ["back"] => cues.back(pattern),
+            ["jump", name] => cues.jump(pattern, name)?,
+            _ => return Err(format!("Unknown cue command: {}", command)),
+        }
+        return Ok(true);
+    }
+    if let Some(command) = msg.strip_prefix(" scene ") {
+        match command.split_whitespace().collect::<Vec<&str>>()[..] {
+            [name] => scenes::recall(pattern, stage, name, 0.0)?,
+            ["snapshot", name] => scenes::snapshot(pattern, stage, name)?,
+            [name, "fade", seconds] => {
+                let seconds = catch_bail!(seconds.parse::<f64>(), format!("Could not parse fade seconds: {}", seconds));
+                scenes::recall(pattern, stage, name, seconds)?
+            },
+            _ => return Err(format!("Unknown scene command: {}", command)),
         }
         return Ok(true);
     }
+    if msg == "pre" {
+        fire("pre", &pattern.pre_events);
+        return Ok(true);
+    }
     let args = msg.split_whitespace().collect::<Vec<&str>>();
     let mut args_iter = args.into_iter();
     let window = args_iter.next().unwrap();
     let action = args_iter.next().unwrap();
     let args = args_iter.collect::<Vec<&str>>();
+    let output = none_bail!(stage.windows.get(window), format!("Unknown window: {}", window));
     match action {
         "show" => {
-            let (win, _) = none_bail!(windows.get(window), format!("Unknown window: {}", action));
-            let wc = win.clone();
-            let wrc: &RefCell<gtk::Window>= wc.borrow();
-            let window: RefMut<gtk::Window> = wrc.borrow_mut();
-            window.show_all();
+            output.window.show_all();
         }
         "move" => {
+            if args.len() != 9 {
+                return Err(format!("move expects 9 arguments, got {}", args.len()));
+            }
             let key = args[0];
             let (pipeline, _) = none_bail!(pattern.pipes.get(key), format!("Pipeline not found: {}", key));
             let pipeline = pipeline.clone().dynamic_cast::<gstreamer::Pipeline>().unwrap();
-            let start_time = (args[1].parse::<f32>().unwrap() * 1_000_000_000.0) as i64;
-            let start_x = args[2].parse::<i32>().unwrap();
-            let start_y = args[3].parse::<i32>().unwrap();
-            let final_time = (args[4].parse::<f32>().unwrap() * 1_000_000_000.0) as i64;
-            let final_x = args[5].parse::<i32>().unwrap();
-            let final_y = args[6].parse::<i32>().unwrap();
+            let start_time = (catch_bail!(args[1].parse::<f32>(), format!("Could not parse start time: {}", args[1])) * 1_000_000_000.0) as i64;
+            // "from current" samples the window position when the action fires
+            let (start_x, start_y) = if args[2] == "from" && args[3] == "current" {
+                output.window.position()
+            } else {
+                (
+                    output.placement.position(Axis::X, Coord::parse(args[2])?),
+                    output.placement.position(Axis::Y, Coord::parse(args[3])?),
+                )
+            };
+            let final_time = (catch_bail!(args[4].parse::<f32>(), format!("Could not parse end time: {}", args[4])) * 1_000_000_000.0) as i64;
+            let final_x = output.placement.position(Axis::X, Coord::parse(args[5])?);
+            let final_y = output.placement.position(Axis::Y, Coord::parse(args[6])?);
             let mode_x = args[7];
             let mode_y = args[8];
-            let part = MovingPart {
+            let part = MovingPart::new(pipeline, start_time, final_time, (start_x, start_y), (final_x, final_y), mode_x, mode_y);
+            motion::animate(output, part);
+        }
+        "move-by" => {
+            if args.len() != 7 {
+                return Err(format!("move-by expects 7 arguments, got {}", args.len()));
+            }
+            let dx = output.placement.length(Axis::X, Coord::parse(args[0])?);
+            let dy = output.placement.length(Axis::Y, Coord::parse(args[1])?);
+            let key = args[2];
+            let (pipeline, _) = none_bail!(pattern.pipes.get(key), format!("Pipeline not found: {}", key));
+            let pipeline = pipeline.clone().dynamic_cast::<gstreamer::Pipeline>().unwrap();
+            let start_time = catch_bail!(args[3].parse::<f32>(), format!("Could not parse start time: {}", args[3]));
+            let final_time = catch_bail!(args[4].parse::<f32>(), format!("Could not parse end time: {}", args[4]));
+            let (start_x, start_y) = output.window.position();
+            let part = MovingPart::new(
                 pipeline,
-                window: window.to_string(),
-                start: start_time,
-                end: final_time,
-                start_x,
-                start_y,
-                end_x: final_x,
-                end_y: final_y,
-                path_x: mode_x.to_string(),
-                path_y: mode_y.to_string(),
-            };
-            parts.push(part);
+                (start_time * 1_000_000_000.0) as i64,
+                (final_time * 1_000_000_000.0) as i64,
+                (start_x, start_y),
+                (start_x + dx, start_y + dy),
+                args[5],
+                args[6],
+            );
+            motion::animate(output, part);
+        }
+        "goto" => {
+            if args.len() < 4 || args.len() > 5 {
+                return Err(format!("goto expects 4 or 5 arguments, got {}", args.len()));
+            }
+            let cell = none_bail!(stage.cells.get(args[0]), format!("Unknown cell: {}", args[0]));
+            let (start, end) = parse_span(&args[2..4])?;
+            let mode = args.get(4).unwrap_or(&"mcos");
+            let part = MovingPart::new(pipeline_of(pattern, args[1])?, start, end, output.window.position(), cell.centre(output.window.size()), mode, mode);
+            motion::animate(output, part);
+        }
+        "swap" => {
+            if args.len() < 4 || args.len() > 5 {
+                return Err(format!("swap expects 4 or 5 arguments, got {}", args.len()));
+            }
+            let other = none_bail!(stage.windows.get(args[0]), format!("Unknown window: {}", args[0]));
+            let pipeline = pipeline_of(pattern, args[1])?;
+            let (start, end) = parse_span(&args[2..4])?;
+            let mode = args.get(4).unwrap_or(&"mcos");
+            let (a, b) = (output.window.position(), other.window.position());
+            motion::animate(output, MovingPart::new(pipeline.clone(), start, end, a, b, mode, mode));
+            motion::animate(other, MovingPart::new(pipeline, start, end, b, a, mode, mode));
+        }
+        "shape" => {
+            let shape = shape::Shape::parse(&args, &pattern.masks)?;
+            match &output.window {
+                Surface::Gtk(window) => shape::apply(window, &shape, 1.0)?,
+                Surface::Logical(window) => window.set_shape(args.join(" ")),
+            }
+        }
+        "shape-scale" => {
+            if args.len() < 7 {
+                return Err(format!("shape-scale expects at least 7 arguments, got {}", args.len()));
+            }
+            let key = args[0];
+            let (pipeline, _) = none_bail!(pattern.pipes.get(key), format!("Pipeline not found: {}", key));
+            let pipeline = pipeline.clone().dynamic_cast::<gstreamer::Pipeline>().unwrap();
+            let start_time = catch_bail!(args[1].parse::<f32>(), format!("Could not parse start time: {}", args[1]));
+            let final_time = catch_bail!(args[2].parse::<f32>(), format!("Could not parse end time: {}", args[2]));
+            let from = catch_bail!(args[3].parse::<f64>(), format!("Could not parse start scale: {}", args[3]));
+            let to = catch_bail!(args[4].parse::<f64>(), format!("Could not parse end scale: {}", args[4]));
+            let shape = shape::Shape::parse(&args[6..], &pattern.masks)?;
+            let anim = shape::ShapeScale::new(
+                pipeline,
+                (start_time * 1_000_000_000.0) as i64,
+                (final_time * 1_000_000_000.0) as i64,
+                from,
+                to,
+                args[5],
+                shape,
+            );
+            match &output.window {
+                Surface::Gtk(window) => shape::animate(window, anim),
+                // the shape as it ends up
+                Surface::Logical(window) => window.set_shape(format!("{} scale {}", args[6..].join(" "), to)),
+            }
         }
         _ => return Err(format!("Unknown action: {}", action))
     }
     return Ok(true);
 }
+
+// Formats a result as a control socket reply.
+fn reply(result: Result<String, String>) -> String {
+    match result {
+        Ok(r) if r.is_empty() => "ok".to_string(),
+        Ok(r) => format!("ok {}", r),
+        Err(e) => format!("error {}", e),
+    }
+}
+
+// Applies lines typed at the console to the running pattern, opening windows
+// for any new window pipelines. Actions and queries go to handle_control.
+fn handle_console(pattern: &Rc<RefCell<Pattern>>, stage: &mut Stage, timers: &mut Timers, cues: &mut CueStack, line: &str) -> Result<String, String> {
+    match line.split_whitespace().next() {
+        Some("act") | Some("query") => return handle_control(&pattern.borrow(), stage, timers, cues, line),
+        _ => (),
+    }
+    // grids, signals, MIDI and pointer input are set up once at startup
+    let args = line.split_whitespace().collect::<Vec<&str>>();
+    let kind = match args[..] {
+        ["layout", ..] => Some("layout"),
+        ["on", "signal", ..] => Some("on signal"),
+        ["on", "midi", ..] if pattern.borrow().midi_events.is_empty() => Some("on midi"),
+        ["on", "click", ..] => Some("on click"),
+        ["on", "hover", ..] => Some("on hover"),
+        _ => None,
+    };
+    if let Some(kind) = kind {
+        return Err(format!("{} needs a restart", kind));
+    }
+    if let Err(err) = pattern.borrow_mut().apply(line) {
+        return Err(err.to_string());
+    }
+    open_new_outputs(pattern, stage)?;
+    Ok("".to_string())
+}
+
+// Opens windows for pipelines added since the stage was set up.
+fn open_new_outputs(pattern: &Rc<RefCell<Pattern>>, stage: &mut Stage) -> Result<(), String> {
+    let names = pattern.borrow().pipes.keys().cloned().collect::<Vec<String>>();
+    for name in names {
+        if stage.windows.contains_key(&name) {
+            continue;
+        }
+        if let Some(output) = open_output(pattern, name.as_str(), stage.headless)? {
+            stage.windows.insert(name, output);
+        }
+    }
+    Ok(())
+}
+
+// Runs an action line or answers a query from the control socket. Window
+// actions are handled straight away so their errors reach the reply.
+fn handle_control(pattern: &Pattern, stage: &Stage, timers: &mut Timers, cues: &mut CueStack, line: &str) -> Result<String, String> {
+    let args = line.split_whitespace().collect::<Vec<&str>>();
+    match args.first().copied().unwrap_or_default() {
+        "act" => {
+            if args.len() < 2 {
+                return Err("act expects a pipeline".to_string());
+            }
+            let (tx, rx) = crossbeam_channel::unbounded();
+            let event = match parse_single_event(pattern, tx, &args) {
+                Ok(e) => e,
+                Err(err) => return Err(err.to_string()),
+            };
+            event.exec();
+            while let Ok(msg) = rx.try_recv() {
+                handle_message(pattern, stage, timers, cues, msg)?;
+            }
+            Ok("".to_string())
+        },
+        "query" => match (args.get(1), args.get(2)) {
+            (Some(&"pipes"), None) => {
+                let mut names = pattern.pipes.keys().cloned().collect::<Vec<String>>();
+                names.sort();
+                Ok(names.join(" "))
+            },
+            (Some(&"windows"), None) => {
+                let mut names = stage.windows.keys().cloned().collect::<Vec<String>>();
+                names.sort();
+                Ok(names.join(" "))
+            },
+            (Some(&"position"), Some(key)) => {
+                let pipeline = pipeline_of(pattern, key)?;
+                let position = none_bail!(pipeline.query_position::<gstreamer::ClockTime>(), format!("No position for {}", key));
+                Ok(format!("{:.3}", position.nseconds() as f64 / 1_000_000_000.0))
+            },
+            (Some(&"state"), Some(key)) => {
+                let pipeline = pipeline_of(pattern, key)?;
+                Ok(format!("{:?}", pipeline.current_state()).to_lowercase())
+            },
+            (Some(&"cues"), None) => Ok(pattern.cues.iter().map(|c| c.name.clone()).collect::<Vec<String>>().join(" ")),
+            (Some(&"cue"), None) => {
+                let (current, standby) = cues.describe(pattern);
+                Ok(format!("{} {}", current.unwrap_or("-"), standby.unwrap_or("-")))
+            },
+            (Some(&"window"), Some(name)) => {
+                let output = none_bail!(stage.windows.get(*name), format!("Unknown window: {}", name));
+                let (x, y) = output.window.position();
+                let (width, height) = output.window.size();
+                let state = format!("{} {} {} {} {}", x, y, width, height, output.window.is_visible());
+                match &output.window {
+                    Surface::Logical(window) => Ok(format!("{} {}", state, window.shape())),
+                    Surface::Gtk(_) => Ok(state),
+                }
+            },
+            _ => Err(format!("Unknown query: {}", args[1..].join(" "))),
+        },
+        a => Err(format!("Unknown command: {}", a)),
+    }
+}
+
+fn pipeline_of(pattern: &Pattern, key: &str) -> Result<gstreamer::Pipeline, String> {
+    let (pipeline, _) = none_bail!(pattern.pipes.get(key), format!("Pipeline not found: {}", key));
+    Ok(pipeline.clone())
+}
+
+// Parses start and end times in seconds to nanoseconds.
+fn parse_span(args: &[&str]) -> Result<(i64, i64), String> {
+    let start = catch_bail!(args[0].parse::<f64>(), format!("Could not parse start time: {}", args[0]));
+    let end = catch_bail!(args[1].parse::<f64>(), format!("Could not parse end time: {}", args[1]));
+    Ok(((start * 1_000_000_000.0) as i64, (end * 1_000_000_000.0) as i64))
+}
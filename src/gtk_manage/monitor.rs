@@ -0,0 +1,132 @@
+/*
+ * Copyright 2022 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use crate::script::ParsedSetting;
+
+pub enum Axis {
+    X,
+    Y,
+}
+
+// A window coordinate, either in pixels or as a fraction of the monitor size.
+#[derive(Clone, Copy)]
+pub enum Coord {
+    Pixels(i32),
+    Fraction(f64),
+}
+
+impl Coord {
+    pub fn parse(s: &str) -> Result<Coord, String> {
+        if let Ok(i) = s.parse::<i32>() {
+            return Ok(Coord::Pixels(i));
+        }
+        match s.parse::<f64>() {
+            Ok(f) => Ok(Coord::Fraction(f)),
+            Err(_) => Err(format!("Could not parse coordinate: {}", s)),
+        }
+    }
+
+    pub fn from_setting(setting: &ParsedSetting) -> Result<Coord, String> {
+        match setting {
+            ParsedSetting::Int(i) => Ok(Coord::Pixels(*i)),
+            ParsedSetting::Float(f) => Ok(Coord::Fraction(*f)),
+            ParsedSetting::String(s) => Coord::parse(s),
+        }
+    }
+}
+
+// The default display, or None when running headless without GTK.
+pub fn display() -> Option<gtk::gdk::Display> {
+    if !gtk::is_initialized() {
+        return None;
+    }
+    gtk::gdk::Display::default()
+}
+
+// The area window coordinates are relative to.
+#[derive(Clone, Copy)]
+pub struct Placement {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Placement {
+    // Global desktop coordinates. Fractions are of the primary monitor.
+    pub fn desktop() -> Placement {
+        let geometry = display()
+            .and_then(|d| d.primary_monitor().or_else(|| d.monitor(0)))
+            .map(|m| m.geometry());
+        match geometry {
+            Some(g) => Placement { x: 0, y: 0, width: g.width(), height: g.height() },
+            None => Placement { x: 0, y: 0, width: 1920, height: 1080 },
+        }
+    }
+
+    // Finds a monitor by its connector name (e.g. HDMI-1) or index. Without
+    // a display every monitor is the desktop.
+    pub fn monitor(name: &str) -> Result<Placement, String> {
+        let display = match display() {
+            Some(d) => d,
+            None => return Ok(Placement::desktop()),
+        };
+        for i in 0..display.n_monitors() {
+            let monitor = match display.monitor(i) {
+                Some(m) => m,
+                None => continue,
+            };
+            let connector = monitor.model().map(|m| m.to_string()).unwrap_or_default();
+            if connector == name || i.to_string() == name {
+                let g = monitor.geometry();
+                return Ok(Placement { x: g.x(), y: g.y(), width: g.width(), height: g.height() });
+            }
+        }
+        Err(format!("Unknown monitor: {}", name))
+    }
+
+    // A distance along the axis.
+    pub fn length(&self, axis: Axis, coord: Coord) -> i32 {
+        match coord {
+            Coord::Pixels(i) => i,
+            Coord::Fraction(f) => match axis {
+                Axis::X => (f * self.width as f64).round() as i32,
+                Axis::Y => (f * self.height as f64).round() as i32,
+            },
+        }
+    }
+
+    // A global position along the axis.
+    pub fn position(&self, axis: Axis, coord: Coord) -> i32 {
+        match axis {
+            Axis::X => self.x + self.length(Axis::X, coord),
+            Axis::Y => self.y + self.length(Axis::Y, coord),
+        }
+    }
+}
+
+pub fn print_monitors() {
+    let display = match display() {
+        Some(d) => d,
+        None => return,
+    };
+    for i in 0..display.n_monitors() {
+        if let Some(monitor) = display.monitor(i) {
+            let g = monitor.geometry();
+            println!("Monitor {}: {} {}x{}+{}+{}", i, monitor.model().map(|m| m.to_string()).unwrap_or_default(), g.width(), g.height(), g.x(), g.y());
+        }
+    }
+}
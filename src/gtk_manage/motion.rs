@@ -0,0 +1,161 @@
+/*
+ * Copyright 2022 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::cell::Cell;
+use std::time::Duration;
+
+use gstreamer::prelude::ElementExtManual;
+use gtk::prelude::WidgetExtManual;
+use gtk::traits::*;
+
+use crate::gtk_manage::window::{OutputWindow, Surface};
+
+// Re-query the pipeline position at least this often, so pauses and missed
+// seeks don't let the running time estimate drift.
+const ANCHOR_NANOS: i64 = 1_000_000_000;
+
+// Follows a pipeline's position for animations.
+pub struct Timeline {
+    pipeline: gstreamer::Pipeline,
+    // (running time, pipeline position, playback rate) sampled together
+    anchor: Cell<Option<(i64, i64, f64)>>,
+}
+
+impl Timeline {
+    pub fn new(pipeline: gstreamer::Pipeline) -> Timeline {
+        Timeline {
+            pipeline,
+            anchor: Cell::new(None),
+        }
+    }
+
+    // Estimates the pipeline position from its running time. Querying the
+    // position directly only advances once per buffer, which judders.
+    pub fn position(&self) -> Option<i64> {
+        if self.pipeline.current_state() != gstreamer::State::Playing {
+            self.anchor.set(None);
+            return self.pipeline.query_position::<gstreamer::ClockTime>().map(|t| t.nseconds() as i64);
+        }
+        let running = match self.pipeline.current_running_time() {
+            Some(t) => t.nseconds() as i64,
+            None => return self.pipeline.query_position::<gstreamer::ClockTime>().map(|t| t.nseconds() as i64),
+        };
+        if let Some((anchor_running, anchor_position, rate)) = self.anchor.get() {
+            // running time goes backwards after flushing seeks
+            if running >= anchor_running && running - anchor_running < ANCHOR_NANOS {
+                return Some(anchor_position + ((running - anchor_running) as f64 * rate) as i64);
+            }
+        }
+        let position = self.pipeline.query_position::<gstreamer::ClockTime>()?.nseconds() as i64;
+        self.anchor.set(Some((running, position, self.rate())));
+        Some(position)
+    }
+
+    // The rate of the current segment, so seeks at other speeds keep the
+    // estimate in step.
+    fn rate(&self) -> f64 {
+        let mut query = gstreamer::query::Segment::new(gstreamer::Format::Time);
+        if self.pipeline.query(&mut query) {
+            query.result().0
+        } else {
+            1.0
+        }
+    }
+}
+
+pub struct MovingPart {
+    timeline: Timeline,
+    start: i64,
+    end: i64,
+    start_x: i32,
+    end_x: i32,
+    start_y: i32,
+    end_y: i32,
+    path_x: String,
+    path_y: String,
+}
+
+impl MovingPart {
+    pub fn new(pipeline: gstreamer::Pipeline, start: i64, end: i64, start_xy: (i32, i32), end_xy: (i32, i32), path_x: &str, path_y: &str) -> MovingPart {
+        MovingPart {
+            timeline: Timeline::new(pipeline),
+            start,
+            end,
+            start_x: start_xy.0,
+            end_x: end_xy.0,
+            start_y: start_xy.1,
+            end_y: end_xy.1,
+            path_x: path_x.to_string(),
+            path_y: path_y.to_string(),
+        }
+    }
+
+    // Returns the window coordinates at the given position, and whether the
+    // motion has finished.
+    fn coordinates(&self, time: i64) -> ((i32, i32), bool) {
+        if time >= self.end {
+            return ((self.end_x, self.end_y), true);
+        }
+        let time_frac = (time - self.start) as f64 / (self.end - self.start) as f64;
+        let x_diff = self.end_x - self.start_x;
+        let y_diff = self.end_y - self.start_y;
+        let x_abs = self.start_x + (x_diff as f64 * ease(self.path_x.as_str(), time_frac)) as i32;
+        let y_abs = self.start_y + (y_diff as f64 * ease(self.path_y.as_str(), time_frac)) as i32;
+        ((x_abs, y_abs), false)
+    }
+}
+
+// Maps the elapsed fraction of an animation onto the fraction of the
+// distance covered along the named path.
+pub fn ease(path: &str, time_frac: f64) -> f64 {
+    match path {
+        "mcos" => 1.0 - f64::to_radians(time_frac * 90.0).cos(),
+        _ => 1.0,
+    }
+}
+
+// Drives a window along a moving part on the window's frame clock, so the
+// window is repositioned once per displayed frame until the motion ends.
+// Headless windows have no frame clock and follow a timer instead. A new
+// motion on the window replaces any still running.
+pub fn animate(output: &OutputWindow, part: MovingPart) {
+    let motion = output.motion.clone();
+    let id = motion.get() + 1;
+    motion.set(id);
+    match &output.window {
+        Surface::Gtk(window) => {
+            window.add_tick_callback(move |window, _| gtk::glib::Continue(motion.get() == id && step(&part, |x, y| window.move_(x, y))));
+        },
+        Surface::Logical(window) => {
+            let window = window.clone();
+            gtk::glib::timeout_add_local(Duration::from_millis(16), move || gtk::glib::Continue(motion.get() == id && step(&part, |x, y| window.move_(x, y))));
+        },
+    }
+}
+
+// Moves the window to where the part is now, returning false once it's done.
+fn step<F: Fn(i32, i32)>(part: &MovingPart, move_: F) -> bool {
+    let time = match part.timeline.position() {
+        Some(t) => t,
+        None => return true,
+    };
+    if time < part.start {
+        return true;
+    }
+    let ((x, y), done) = part.coordinates(time);
+    move_(x, y);
+    !done
+}
@@ -0,0 +1,89 @@
+/*
+ * Copyright 2022 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::ffi::OsString;
+
+pub static USAGE: &str = "Usage: live-mix [--control-window] [--control SOCKET_PATH] [--osc PORT [--osc-lan]] [--http PORT] [--repl] [--watch] [--headless] PATTERN_PATH";
+
+#[derive(Clone)]
+pub struct Options {
+    pub path: String,
+    // a small window that takes hotkeys without covering an output
+    pub control_window: bool,
+    // a Unix socket that accepts action lines and queries
+    pub control: Option<String>,
+    // the UDP port to receive OSC on
+    pub osc: Option<u16>,
+    // accept OSC from other machines, not just this one
+    pub osc_lan: bool,
+    // the localhost port for the remote control API
+    pub http: Option<u16>,
+    // read pattern lines from stdin while running
+    pub repl: bool,
+    // reload the pattern file when it changes
+    pub watch: bool,
+    // run without a display, tracking windows instead of opening them
+    pub headless: bool,
+}
+
+impl Options {
+    pub fn parse(args: &[OsString]) -> Result<Options, String> {
+        let mut path = None;
+        let mut control_window = false;
+        let mut control = None;
+        let mut osc = None;
+        let mut osc_lan = false;
+        let mut http = None;
+        let mut repl = false;
+        let mut watch = false;
+        let mut headless = false;
+        let mut iter = args.iter().skip(1).map(|a| a.clone().into_string());
+        while let Some(arg) = iter.next() {
+            let arg = match arg {
+                Ok(a) => a,
+                Err(_) => return Err("Couldn't parse arguments".to_string()),
+            };
+            match arg.as_str() {
+                "--control-window" => control_window = true,
+                "--repl" => repl = true,
+                "--osc-lan" => osc_lan = true,
+                "--watch" => watch = true,
+                "--headless" => headless = true,
+                "--control" => match iter.next() {
+                    Some(Ok(p)) => control = Some(p),
+                    _ => return Err("--control expects a socket path".to_string()),
+                },
+                "--osc" => match iter.next().map(|p| p.map(|p| p.parse::<u16>())) {
+                    Some(Ok(Ok(port))) => osc = Some(port),
+                    _ => return Err("--osc expects a port number".to_string()),
+                },
+                "--http" => match iter.next().map(|p| p.map(|p| p.parse::<u16>())) {
+                    Some(Ok(Ok(port))) => http = Some(port),
+                    _ => return Err("--http expects a port number".to_string()),
+                },
+                a if a.starts_with("--") => return Err(format!("Unknown option: {}", a)),
+                _ => path = Some(arg),
+            }
+        }
+        if headless && control_window {
+            return Err("--control-window needs a display".to_string());
+        }
+        match path {
+            Some(path) => Ok(Options { path, control_window, control, osc, osc_lan, http, repl, watch, headless }),
+            None => Err(USAGE.to_string()),
+        }
+    }
+}
@@ -0,0 +1,101 @@
+/*
+ * Copyright 2022 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+use gstreamer::prelude::ElementExtManual;
+
+use crate::script::{Jump, Pattern, Repeat};
+use crate::script::action::fire;
+
+// A position further ahead of the last one than the wall time since then,
+// plus this slack, counts as a forward seek.
+const JUMP_SLACK_NANOS: i64 = 500_000_000;
+
+// Fires the pattern's progress triggers as their pipelines pass each trigger
+// time, re-arming after backwards seeks and loops.
+pub struct Progress {
+    // the last position seen for each pipeline, and when it was seen
+    positions: HashMap<String, (i64, Instant)>,
+    fired: Vec<bool>,
+}
+
+impl Progress {
+    pub fn new(pattern: &Pattern) -> Progress {
+        Progress {
+            positions: HashMap::new(),
+            fired: vec![false; pattern.time_events.len()],
+        }
+    }
+
+    // Treats every trigger as new after the pattern is reloaded, so only
+    // those still ahead of their pipeline fire.
+    pub fn reload(&mut self) {
+        self.fired.clear();
+    }
+
+    pub fn poll(&mut self, pattern: &Pattern) {
+        let mut current = HashMap::new();
+        for trigger in &pattern.time_events {
+            if current.contains_key(&trigger.pipe) {
+                continue;
+            }
+            let (p, _) = match pattern.pipes.get(&trigger.pipe) {
+                Some(p) => p,
+                None => panic!("Could not find node {}", trigger.pipe),
+            };
+            if let Some(d) = p.query_position_generic(gstreamer::Format::Time) {
+                current.insert(trigger.pipe.clone(), d.value());
+            }
+        }
+
+        // triggers added from the console don't fire for times already passed
+        for trigger in &pattern.time_events[self.fired.len()..] {
+            let passed = current.get(&trigger.pipe).map(|p| *p > trigger.nanos as i64).unwrap_or(false);
+            self.fired.push(passed);
+        }
+
+        for (trigger, fired) in pattern.time_events.iter().zip(self.fired.iter_mut()) {
+            let position = match current.get(&trigger.pipe) {
+                Some(p) => *p,
+                None => continue,
+            };
+            let nanos = trigger.nanos as i64;
+            let (last, jumped) = match self.positions.get(&trigger.pipe) {
+                Some((last, seen)) => (*last, position - last > seen.elapsed().as_nanos() as i64 + JUMP_SLACK_NANOS),
+                None => (position, false),
+            };
+            if position < last && position <= nanos && trigger.repeat == Repeat::EveryPass {
+                *fired = false;
+            }
+            if *fired || position <= nanos {
+                continue;
+            }
+            *fired = true;
+            if jumped && last <= nanos && trigger.jump == Jump::Skip {
+                println!("Skipped progress trigger at {} on {}", nanos, trigger.pipe);
+                continue;
+            }
+            fire(format!("progress {} {}", trigger.pipe, nanos as f64 / 1_000_000_000.0).as_str(), &trigger.events);
+        }
+
+        let now = Instant::now();
+        for (name, position) in current {
+            self.positions.insert(name, (position, now));
+        }
+    }
+}
@@ -0,0 +1,215 @@
+/*
+ * Copyright 2022 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::rc::Rc;
+use std::{env, mem};
+
+use gstreamer::prelude::{Cast, ElementExt, ElementExtManual};
+use gtk::glib::ObjectExt;
+
+use crate::gtk_manage::{open_new_outputs, Stage};
+use crate::script::Pattern;
+
+pub enum Reload {
+    Applied,
+    // the change can't be made in place, for the given reason
+    Restart(String),
+}
+
+// The lines of a pattern file, grouped by what a reload does with them.
+struct Outline<'a> {
+    // raw blocks by template name
+    templates: HashMap<&'a str, String>,
+    // pipeline name, template, arguments and the whole new line
+    pipes: Vec<(&'a str, &'a str, Vec<&'a str>, &'a str)>,
+    plugs: Vec<&'a str>,
+    layouts: Vec<&'a str>,
+    // triggers, tempo and anything else, rebuilt on every reload
+    rest: Vec<&'a str>,
+}
+
+impl<'a> Outline<'a> {
+    fn parse(text: &'a str) -> Outline<'a> {
+        let mut outline = Outline {
+            templates: HashMap::new(),
+            pipes: Vec::new(),
+            plugs: Vec::new(),
+            layouts: Vec::new(),
+            rest: Vec::new(),
+        };
+        let mut lines = text.split('\n').filter(|l| !l.starts_with("//") && !l.is_empty());
+        while let Some(line) = lines.next() {
+            let args = line.split_whitespace().collect::<Vec<&str>>();
+            match args[..] {
+                ["raw", name, ..] => {
+                    let mut block = vec![line];
+                    for l in lines.by_ref() {
+                        block.push(l);
+                        if l.starts_with("war") {
+                            break;
+                        }
+                    }
+                    outline.templates.insert(name, block.join("\n"));
+                },
+                ["new", template, name, ref args @ ..] => outline.pipes.push((name, template, args.to_vec(), line)),
+                ["plug", ..] => outline.plugs.push(line),
+                ["layout", ..] => outline.layouts.push(line),
+                _ => outline.rest.push(line),
+            }
+        }
+        outline
+    }
+}
+
+// Applies the changes between two versions of the pattern file to the running
+// pattern. Pipelines whose template and arguments haven't changed keep
+// running, and changed arguments are set on the existing pipeline.
+pub fn reload(pattern: &Rc<RefCell<Pattern>>, stage: &mut Stage, old_text: &str, new_text: &str) -> Result<Reload, String> {
+    let old = Outline::parse(old_text);
+    let new = Outline::parse(new_text);
+    if old.layouts != new.layouts {
+        return Ok(Reload::Restart("layouts changed".to_string()));
+    }
+
+    let current = pattern.borrow();
+    let mut next = current.successor();
+    let templates = new.templates.values().cloned().collect::<Vec<String>>().join("\n");
+    if let Err(err) = next.apply(&templates) {
+        return Err(err.to_string());
+    }
+
+    let mut lines = Vec::new();
+    // running pipelines with new arguments, set once nothing can stop the reload
+    let mut changed = Vec::new();
+    for (name, template, args, line) in &new.pipes {
+        let before = old.pipes.iter().find(|(n, _, _, _)| n == name);
+        let running = current.pipes.get(*name);
+        let same_template = old.templates.get(template) == new.templates.get(template);
+        match (before, running) {
+            (Some((_, t, a, _)), Some((elem, settings))) if t == template && same_template => {
+                if a == args {
+                    next.pipes.insert(name.to_string(), (elem.clone(), settings.clone()));
+                    continue;
+                }
+                let block = match next.blocks.get(*template) {
+                    Some(b) => b,
+                    None => return Err(format!("Unknown template: {}", template)),
+                };
+                let pipeline = elem.clone().dynamic_cast::<gstreamer::Pipeline>().unwrap();
+                let external = match block.check(&pipeline, args) {
+                    Ok(e) => e,
+                    Err(err) => return Err(err.to_string()),
+                };
+                if stage.windows.contains_key(*name) && external != *settings {
+                    return Ok(Reload::Restart(format!("window settings of {} changed", name)));
+                }
+                next.pipes.insert(name.to_string(), (elem.clone(), external));
+                changed.push((*name, pipeline, *template, args));
+            },
+            _ => lines.push(*line),
+        }
+    }
+    lines.extend(&new.rest);
+    if let Err(err) = next.apply(&lines.join("\n")) {
+        next.disconnect_handlers();
+        return Err(err.to_string());
+    }
+    // plugs rewire running pipelines, so are only made once nothing can stop the reload
+    let mut plugs = Vec::new();
+    for line in &new.plugs {
+        let args = line.split_whitespace().collect::<Vec<&str>>();
+        match next.plug_ends(&args) {
+            Ok(ends) => plugs.push(ends),
+            Err(err) => {
+                next.disconnect_handlers();
+                return Err(err.to_string());
+            },
+        }
+    }
+
+    // pipelines that are replaced or gone, with the state they were in
+    let mut retired = Vec::new();
+    let mut reason = None;
+    for (name, (elem, _)) in &current.pipes {
+        if next.pipes.get(name).map(|(e, _)| e == elem).unwrap_or(false) {
+            continue;
+        }
+        if stage.windows.contains_key(name) {
+            reason = Some(format!("window pipeline {} changed", name));
+        }
+        retired.push((name.clone(), elem.clone()));
+    }
+    // these are connected once at startup
+    let signals = current.signal_events.iter().map(|t| t.signal).collect::<HashSet<i32>>();
+    if next.signal_events.iter().any(|t| !signals.contains(&t.signal)) {
+        reason = Some("new signal triggers".to_string());
+    }
+    if current.midi_events.is_empty() && !next.midi_events.is_empty() {
+        reason = Some("new midi triggers".to_string());
+    }
+    let pointers = |p: &Pattern| p.pointer_events.iter().map(|t| t.window.clone()).collect::<HashSet<String>>();
+    if pointers(&current) != pointers(&next) {
+        reason = Some("windows taking clicks changed".to_string());
+    }
+    if let Some(reason) = reason {
+        next.disconnect_handlers();
+        return Ok(Reload::Restart(reason));
+    }
+
+    for (name, pipeline, template, args) in changed {
+        // checked above, so this only fails if an element changed under us
+        if let Err(err) = next.blocks[template].configure(&pipeline, args) {
+            println!("Error setting new arguments of {}: {}", name, err);
+        }
+    }
+    for (sink, src) in plugs {
+        src.set_property("proxysink", sink);
+    }
+
+    drop(current);
+    let mut previous = mem::replace(&mut *pattern.borrow_mut(), next);
+    previous.disconnect_handlers();
+    for (name, elem) in retired {
+        let state = elem.current_state();
+        let _ = elem.set_state(gstreamer::State::Null);
+        if let Some((replacement, _)) = pattern.borrow().pipes.get(&name) {
+            let _ = replacement.set_state(state);
+        }
+    }
+    open_new_outputs(pattern, stage)?;
+    Ok(Reload::Applied)
+}
+
+// Stops every pipeline and runs the mixer again with the same arguments.
+pub fn restart(pattern: &Pattern, reason: &str) {
+    println!("Restarting: {}", reason);
+    for (elem, _) in pattern.pipes.values() {
+        let _ = elem.set_state(gstreamer::State::Null);
+    }
+    let exe = match env::current_exe() {
+        Ok(e) => e,
+        Err(err) => {
+            println!("Could not find the executable: {}", err);
+            return;
+        }
+    };
+    let err = Command::new(exe).args(env::args_os().skip(1)).exec();
+    println!("Could not restart: {}", err);
+}
@@ -0,0 +1,219 @@
+/*
+ * Copyright 2022 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use gstreamer::prelude::{ElementExt, ElementExtManual, GstObjectExt};
+use gstreamer_video::VideoOrientationMethod;
+use gtk::glib::ObjectExt;
+
+use crate::gtk_manage::Stage;
+use crate::gtk_manage::monitor::{Axis, Coord};
+use crate::script::{Pattern, SceneSetting};
+use crate::script::action::set_property;
+
+thread_local! {
+    // the recall that last set each target, so a newer scene only stops an
+    // older one's fade where they overlap
+    static OWNERS: RefCell<HashMap<String, u64>> = RefCell::new(HashMap::new());
+    static RECALLS: Cell<u64> = const { Cell::new(0) };
+}
+
+// A change applied over a fade, and the target it sets.
+type Tween = (String, Box<dyn Fn(f64)>);
+
+fn owns(target: &str, recall: u64) -> bool {
+    OWNERS.with(|o| o.borrow().get(target) == Some(&recall))
+}
+
+// Forgets the targets a finished recall still owns.
+fn release(recall: u64) {
+    OWNERS.with(|o| o.borrow_mut().retain(|_, r| *r != recall));
+}
+
+// Moves everything a scene names to the scene's state. Over a fade, numbers
+// and window positions are interpolated from where they are now. Pipelines
+// start at the beginning of the fade, and windows are hidden and pipelines
+// stopped at the end of it. A later recall takes over only the targets it
+// shares with a fade still running; the rest of that fade carries on.
+pub fn recall(pattern: &Pattern, stage: &Stage, name: &str, seconds: f64) -> Result<(), String> {
+    let scene = match pattern.scenes.get(name) {
+        Some(s) => s,
+        None => return Err(format!("Unknown scene: {}", name)),
+    };
+    let recall = RECALLS.with(|r| {
+        r.set(r.get() + 1);
+        r.get()
+    });
+    let mut targets = Vec::new();
+    let mut tweens: Vec<Tween> = Vec::new();
+    let mut last: Vec<(String, Box<dyn Fn()>)> = Vec::new();
+    for setting in scene.settings.borrow().iter() {
+        match setting {
+            SceneSetting::Prop { element, prop, type_, value } => {
+                let target = format!("prop {} {}", element.path_string(), prop);
+                targets.push(target.clone());
+                let from = match type_.as_str() {
+                    "int" => element.property_value(prop).get::<i32>().ok().map(|v| v as f64),
+                    "float" => element.property_value(prop).get::<f64>().ok(),
+                    _ => None,
+                };
+                match (from, value.parse::<f64>()) {
+                    (Some(from), Ok(to)) if seconds > 0.0 => {
+                        let (element, prop, type_) = (element.clone(), prop.clone(), type_.clone());
+                        tweens.push((target, Box::new(move |frac| {
+                            let value = from + (to - from) * frac;
+                            let value = if type_ == "int" { format!("{}", value.round() as i32) } else { value.to_string() };
+                            let _ = set_property(element.clone(), prop.clone(), type_.clone(), value);
+                        })));
+                    },
+                    _ => {
+                        if let Err(err) = set_property(element.clone(), prop.clone(), type_.clone(), value.clone()) {
+                            return Err(err.to_string());
+                        }
+                    },
+                }
+            },
+            SceneSetting::State { pipeline, state } => {
+                let target = format!("state {}", pipeline.name());
+                targets.push(target.clone());
+                if *state == gstreamer::State::Playing || seconds <= 0.0 {
+                    let _ = pipeline.set_state(*state);
+                } else {
+                    let (pipeline, state) = (pipeline.clone(), *state);
+                    last.push((target, Box::new(move || {
+                        let _ = pipeline.set_state(state);
+                    })));
+                }
+            },
+            SceneSetting::Visible { window, visible } => {
+                let output = match stage.windows.get(window) {
+                    Some(o) => o.window.clone(),
+                    None => return Err(format!("Unknown window: {}", window)),
+                };
+                let target = format!("visible {}", window);
+                targets.push(target.clone());
+                if *visible {
+                    output.show_all();
+                } else if seconds <= 0.0 {
+                    output.hide();
+                } else {
+                    last.push((target, Box::new(move || output.hide())));
+                }
+            },
+            SceneSetting::Position { window, x, y } => {
+                let output = match stage.windows.get(window) {
+                    Some(o) => o,
+                    None => return Err(format!("Unknown window: {}", window)),
+                };
+                let target = format!("position {}", window);
+                targets.push(target.clone());
+                // stops any motion the window is in, and is stopped by the next
+                let motion = output.motion.clone();
+                motion.set(motion.get() + 1);
+                let id = motion.get();
+                let to_x = output.placement.position(Axis::X, *x);
+                let to_y = output.placement.position(Axis::Y, *y);
+                let (from_x, from_y) = output.window.position();
+                let window = output.window.clone();
+                tweens.push((target, Box::new(move |frac| {
+                    if motion.get() != id {
+                        return;
+                    }
+                    let x = from_x + ((to_x - from_x) as f64 * frac) as i32;
+                    let y = from_y + ((to_y - from_y) as f64 * frac) as i32;
+                    window.move_(x, y);
+                })));
+            },
+        }
+    }
+
+    OWNERS.with(|o| {
+        let mut owners = o.borrow_mut();
+        for target in targets {
+            owners.insert(target, recall);
+        }
+    });
+    if seconds <= 0.0 {
+        tweens.iter().for_each(|(_, t)| t(1.0));
+        release(recall);
+        return Ok(());
+    }
+    let start = Instant::now();
+    gtk::glib::timeout_add_local(Duration::from_millis(16), move || {
+        // a newer scene has taken over everything this one was fading
+        if !tweens.iter().map(|(t, _)| t).chain(last.iter().map(|(t, _)| t)).any(|t| owns(t, recall)) {
+            return gtk::glib::Continue(false);
+        }
+        let frac = (start.elapsed().as_secs_f64() / seconds).min(1.0);
+        tweens.iter().filter(|(t, _)| owns(t, recall)).for_each(|(_, t)| t(frac));
+        if frac < 1.0 {
+            return gtk::glib::Continue(true);
+        }
+        last.iter().filter(|(t, _)| owns(t, recall)).for_each(|(_, l)| l());
+        release(recall);
+        gtk::glib::Continue(false)
+    });
+    Ok(())
+}
+
+// Stores the current state of everything a scene names as the scene's state,
+// so a later recall returns to it.
+pub fn snapshot(pattern: &Pattern, stage: &Stage, name: &str) -> Result<(), String> {
+    let scene = match pattern.scenes.get(name) {
+        Some(s) => s,
+        None => return Err(format!("Unknown scene: {}", name)),
+    };
+    for setting in scene.settings.borrow_mut().iter_mut() {
+        match setting {
+            SceneSetting::Prop { element, prop, type_, value } => {
+                let current = element.property_value(prop);
+                *value = match type_.as_str() {
+                    "int" => current.get::<i32>().map(|v| v.to_string()).map_err(|e| e.to_string())?,
+                    "float" => current.get::<f64>().map(|v| v.to_string()).map_err(|e| e.to_string())?,
+                    "string" => current.get::<Option<String>>().map_err(|e| e.to_string())?.unwrap_or_default(),
+                    "GstOrientation" => match current.get::<VideoOrientationMethod>() {
+                        Ok(VideoOrientationMethod::Identity) => "0".to_string(),
+                        Ok(VideoOrientationMethod::_90r) => "1".to_string(),
+                        Ok(VideoOrientationMethod::_180) => "2".to_string(),
+                        Ok(VideoOrientationMethod::_90l) => "3".to_string(),
+                        _ => return Err(format!("Can't snapshot {} of {}", prop, element.name())),
+                    },
+                    t => return Err(format!("Unknown parameter type: {}", t)),
+                };
+            },
+            SceneSetting::State { pipeline, state } => *state = pipeline.current_state(),
+            SceneSetting::Visible { window, visible } => {
+                match stage.windows.get(window) {
+                    Some(o) => *visible = o.window.is_visible(),
+                    None => return Err(format!("Unknown window: {}", window)),
+                }
+            },
+            SceneSetting::Position { window, x, y } => {
+                let output = match stage.windows.get(window) {
+                    Some(o) => o,
+                    None => return Err(format!("Unknown window: {}", window)),
+                };
+                let (at_x, at_y) = output.window.position();
+                *x = Coord::Pixels(at_x - output.placement.x);
+                *y = Coord::Pixels(at_y - output.placement.y);
+            },
+        }
+    }
+    Ok(())
+}
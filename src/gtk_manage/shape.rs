@@ -0,0 +1,198 @@
+/*
+ * Copyright 2022 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fs::File;
+
+use gtk::gdk::prelude::GdkSurfaceExt;
+use gtk::prelude::WidgetExtManual;
+use gtk::traits::*;
+
+use crate::catch_bail_annotate;
+use crate::gtk_manage::motion::{ease, Timeline};
+
+pub enum Shape {
+    None,
+    Circle,
+    // corner radius in pixels
+    Rounded(f64),
+    // vertices in fractions of the window size
+    Polygon(Vec<(f64, f64)>),
+    Mask(cairo::ImageSurface),
+}
+
+impl Shape {
+    // Masks are read once into the pattern's cache, when the action is parsed.
+    pub fn parse(args: &[&str], masks: &RefCell<HashMap<String, cairo::ImageSurface>>) -> Result<Shape, String> {
+        if args.is_empty() {
+            return Err("No shape given".to_string());
+        }
+        let shape = match args[0] {
+            "none" => Shape::None,
+            "circle" => Shape::Circle,
+            "rounded" => {
+                if args.len() != 2 {
+                    return Err("rounded expects a corner radius".to_string());
+                }
+                Shape::Rounded(catch_bail_annotate!(args[1].parse::<f64>(), "Could not parse corner radius"))
+            },
+            "polygon" => {
+                if args.len() < 7 || args.len() % 2 != 1 {
+                    return Err("polygon expects at least three x y pairs".to_string());
+                }
+                let mut points = Vec::new();
+                for pair in args[1..].chunks(2) {
+                    let x = catch_bail_annotate!(pair[0].parse::<f64>(), "Could not parse polygon x");
+                    let y = catch_bail_annotate!(pair[1].parse::<f64>(), "Could not parse polygon y");
+                    points.push((x, y));
+                }
+                Shape::Polygon(points)
+            },
+            "mask" => {
+                if args.len() != 2 {
+                    return Err("mask expects a png path".to_string());
+                }
+                if let Some(mask) = masks.borrow().get(args[1]) {
+                    return Ok(Shape::Mask(mask.clone()));
+                }
+                let mut file = catch_bail_annotate!(File::open(args[1]), format!("Could not open mask {}", args[1]));
+                let mask = catch_bail_annotate!(cairo::ImageSurface::create_from_png(&mut file), format!("Could not read mask {}", args[1]));
+                masks.borrow_mut().insert(args[1].to_string(), mask.clone());
+                Shape::Mask(mask)
+            },
+            s => return Err(format!("Unknown shape: {}", s)),
+        };
+        Ok(shape)
+    }
+
+    // Fills the shape over a width by height area.
+    fn draw(&self, cr: &cairo::Context, width: f64, height: f64) -> Result<(), cairo::Error> {
+        match self {
+            Shape::None => {
+                cr.rectangle(0.0, 0.0, width, height);
+                cr.fill()
+            },
+            Shape::Circle => {
+                cr.save()?;
+                cr.translate(width / 2.0, height / 2.0);
+                cr.scale(width / 2.0, height / 2.0);
+                cr.arc(0.0, 0.0, 1.0, 0.0, 2.0 * PI);
+                cr.restore()?;
+                cr.fill()
+            },
+            Shape::Rounded(radius) => {
+                let r = radius.min(width / 2.0).min(height / 2.0);
+                cr.new_sub_path();
+                cr.arc(width - r, r, r, -PI / 2.0, 0.0);
+                cr.arc(width - r, height - r, r, 0.0, PI / 2.0);
+                cr.arc(r, height - r, r, PI / 2.0, PI);
+                cr.arc(r, r, r, PI, 3.0 * PI / 2.0);
+                cr.close_path();
+                cr.fill()
+            },
+            Shape::Polygon(points) => {
+                for (x, y) in points {
+                    cr.line_to(x * width, y * height);
+                }
+                cr.close_path();
+                cr.fill()
+            },
+            Shape::Mask(mask) => {
+                cr.scale(width / mask.width() as f64, height / mask.height() as f64);
+                cr.set_source_surface(mask, 0.0, 0.0)?;
+                cr.paint()
+            },
+        }
+    }
+}
+
+// Clips the window, both drawing and input, to the shape scaled around the
+// window centre.
+pub fn apply(window: &gtk::Window, shape: &Shape, scale: f64) -> Result<(), String> {
+    if let Shape::None = shape {
+        window.shape_combine_region(None);
+        window.input_shape_combine_region(None);
+        return Ok(());
+    }
+    let (width, height) = window.size();
+    let surface = catch_bail_annotate!(cairo::ImageSurface::create(cairo::Format::ARgb32, width, height), "Could not create shape surface");
+    {
+        let cr = catch_bail_annotate!(cairo::Context::new(&surface), "Could not create shape context");
+        let (w, h) = (width as f64, height as f64);
+        cr.translate(w / 2.0, h / 2.0);
+        cr.scale(scale, scale);
+        cr.translate(-w / 2.0, -h / 2.0);
+        catch_bail_annotate!(shape.draw(&cr, w, h), "Could not draw shape");
+    }
+    let region = match surface.create_region() {
+        Some(r) => r,
+        None => return Err("Could not create region from shape".to_string()),
+    };
+    window.shape_combine_region(Some(&region));
+    window.input_shape_combine_region(Some(&region));
+    Ok(())
+}
+
+pub struct ShapeScale {
+    timeline: Timeline,
+    start: i64,
+    end: i64,
+    from: f64,
+    to: f64,
+    path: String,
+    shape: Shape,
+}
+
+impl ShapeScale {
+    pub fn new(pipeline: gstreamer::Pipeline, start: i64, end: i64, from: f64, to: f64, path: &str, shape: Shape) -> ShapeScale {
+        ShapeScale {
+            timeline: Timeline::new(pipeline),
+            start,
+            end,
+            from,
+            to,
+            path: path.to_string(),
+            shape,
+        }
+    }
+}
+
+// Grows or shrinks a shape on the window's frame clock, like motion::animate.
+pub fn animate(window: &gtk::Window, anim: ShapeScale) {
+    window.add_tick_callback(move |window, _| {
+        let time = match anim.timeline.position() {
+            Some(t) => t,
+            None => return gtk::glib::Continue(true),
+        };
+        if time < anim.start {
+            return gtk::glib::Continue(true);
+        }
+        let done = time >= anim.end;
+        let scale = if done {
+            anim.to
+        } else {
+            let time_frac = (time - anim.start) as f64 / (anim.end - anim.start) as f64;
+            anim.from + (anim.to - anim.from) * ease(anim.path.as_str(), time_frac)
+        };
+        if let Err(e) = apply(window, &anim.shape, scale) {
+            println!("Error from shape: {}", e);
+            return gtk::glib::Continue(false);
+        }
+        gtk::glib::Continue(!done)
+    });
+}
@@ -0,0 +1,178 @@
+/*
+ * Copyright 2022 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use gstreamer::prelude::ElementExtManual;
+use gtk::glib::DateTime;
+
+use crate::script::{AfterTrigger, EveryTrigger, Pattern};
+use crate::script::action::fire;
+
+// State for the triggers that run off the monotonic and wall clocks rather
+// than a pipeline's position.
+pub struct Timers {
+    start: Instant,
+    marks: HashMap<String, Instant>,
+    // the reference instant each after trigger last fired for
+    after_fired: Vec<Option<Instant>>,
+    // the local date each clock trigger last fired on
+    clock_fired: Vec<Option<(i32, i32, i32)>>,
+    // the last interval each every trigger fired for
+    every_fired: Vec<Option<i64>>,
+}
+
+impl Timers {
+    pub fn new(pattern: &Pattern) -> Timers {
+        let mut timers = Timers {
+            start: Instant::now(),
+            marks: HashMap::new(),
+            after_fired: Vec::new(),
+            clock_fired: Vec::new(),
+            every_fired: Vec::new(),
+        };
+        timers.add_triggers(pattern);
+        timers
+    }
+
+    // Tracks triggers added since the last call, e.g. from the console.
+    fn add_triggers(&mut self, pattern: &Pattern) {
+        self.after_fired.resize(pattern.after_events.len(), None);
+        self.every_fired.resize(pattern.every_events.len(), None);
+        let now = DateTime::now_local().ok();
+        for trigger in &pattern.clock_events[self.clock_fired.len()..] {
+            // times of day that have already passed wait for tomorrow
+            self.clock_fired.push(match &now {
+                Some(now) if seconds_of_day(now) >= trigger.seconds => Some(now.ymd()),
+                _ => None,
+            });
+        }
+    }
+
+    // Starts tracking a reloaded pattern's triggers. Those that would have
+    // fired already by now are counted as fired.
+    pub fn reload(&mut self, pattern: &Pattern) {
+        self.after_fired.clear();
+        self.clock_fired.clear();
+        self.every_fired.clear();
+        self.add_triggers(pattern);
+        for (i, trigger) in pattern.after_events.iter().enumerate() {
+            if let Some(since) = self.since(trigger) {
+                if since.elapsed() >= Duration::from_secs_f64(trigger.seconds) {
+                    self.after_fired[i] = Some(since);
+                }
+            }
+        }
+        for (i, trigger) in pattern.every_events.iter().enumerate() {
+            self.every_fired[i] = self.interval(pattern, trigger);
+        }
+    }
+
+    // Records the mark, re-arming after triggers counting from it, and runs
+    // the mark's own triggers.
+    pub fn mark(&mut self, pattern: &Pattern, name: &str) {
+        self.marks.insert(name.to_string(), Instant::now());
+        for trigger in &pattern.mark_events {
+            if trigger.name == name {
+                fire(format!("mark {}", name).as_str(), &trigger.events);
+            }
+        }
+    }
+
+    pub fn poll(&mut self, pattern: &Pattern) {
+        self.add_triggers(pattern);
+        for (i, trigger) in pattern.after_events.iter().enumerate() {
+            let since = match self.since(trigger) {
+                Some(s) => s,
+                None => continue,
+            };
+            let fired = &mut self.after_fired[i];
+            if *fired == Some(since) || since.elapsed() < Duration::from_secs_f64(trigger.seconds) {
+                continue;
+            }
+            *fired = Some(since);
+            fire(format!("after {}", trigger.seconds).as_str(), &trigger.events);
+        }
+
+        for (i, trigger) in pattern.every_events.iter().enumerate() {
+            let time = match self.time_of(pattern, trigger) {
+                Some(t) => t,
+                None => continue,
+            };
+            let fired = &mut self.every_fired[i];
+            if time < trigger.from || trigger.until.map(|u| time > u).unwrap_or(false) {
+                // seeking back before the start fires the first interval again
+                *fired = None;
+                continue;
+            }
+            let count = ((time - trigger.from) / trigger.interval).floor() as i64;
+            if *fired == Some(count) {
+                continue;
+            }
+            *fired = Some(count);
+            fire(format!("every {}", trigger.interval).as_str(), &trigger.events);
+        }
+
+        let now = match DateTime::now_local() {
+            Ok(n) => n,
+            Err(_) => return,
+        };
+        let (today, seconds) = (now.ymd(), seconds_of_day(&now));
+        for (trigger, fired) in pattern.clock_events.iter().zip(self.clock_fired.iter_mut()) {
+            if *fired == Some(today) || seconds < trigger.seconds {
+                continue;
+            }
+            *fired = Some(today);
+            let at = trigger.seconds;
+            fire(format!("at {:02}:{:02}:{:02}", at / 3600, at / 60 % 60, at % 60).as_str(), &trigger.events);
+        }
+    }
+}
+
+impl Timers {
+    // The instant an after trigger counts from, if its mark has been hit.
+    fn since(&self, trigger: &AfterTrigger) -> Option<Instant> {
+        match &trigger.since {
+            Some(name) => self.marks.get(name).copied(),
+            None => Some(self.start),
+        }
+    }
+
+    // The seconds an every trigger counts in: its pipeline's position, or
+    // the time since startup.
+    fn time_of(&self, pattern: &Pattern, trigger: &EveryTrigger) -> Option<f64> {
+        match &trigger.pipe {
+            Some(name) => pattern.pipes.get(name)
+                .and_then(|(p, _)| p.query_position::<gstreamer::ClockTime>())
+                .map(|p| p.nseconds() as f64 / 1_000_000_000.0),
+            None => Some(self.start.elapsed().as_secs_f64()),
+        }
+    }
+
+    // The interval an every trigger is in now, or None outside its span.
+    fn interval(&self, pattern: &Pattern, trigger: &EveryTrigger) -> Option<i64> {
+        let time = self.time_of(pattern, trigger)?;
+        if time < trigger.from || trigger.until.map(|u| time > u).unwrap_or(false) {
+            return None;
+        }
+        Some(((time - trigger.from) / trigger.interval).floor() as i64)
+    }
+}
+
+fn seconds_of_day(time: &DateTime) -> u32 {
+    (time.hour() * 3600 + time.minute() * 60 + time.second()) as u32
+}
@@ -14,13 +14,157 @@
  * limitations under the License.
 */
 
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use gstreamer::prelude::{ElementExt, GstBinExt, GstBinExtManual, GstObjectExt, PadExt};
+use gtk::glib::ObjectExt;
 use gtk::traits::*;
 
+use crate::gtk_manage::monitor::Placement;
+
 extern {
     fn gdk_x11_window_get_xid(window: gtk::gdk::Window) -> u32;
 }
 
-pub fn create_gtk_window(x: i32, y: i32, width: i32, height: i32) -> (gtk::Window, u32) {
+pub struct OutputWindow {
+    pub window: Surface,
+    // the area the window's coordinates are relative to
+    pub placement: Placement,
+    // bumped when a motion starts, so the one before it stops
+    pub motion: Rc<Cell<u64>>,
+}
+
+// Where an output is shown: a GTK window, or when running headless a window
+// that only exists as its position, size and visibility.
+#[derive(Clone)]
+pub enum Surface {
+    Gtk(gtk::Window),
+    Logical(Rc<LogicalWindow>),
+}
+
+pub struct LogicalWindow {
+    position: Cell<(i32, i32)>,
+    size: (i32, i32),
+    visible: Cell<bool>,
+    // the shape action's arguments, since there is nothing to cut
+    shape: RefCell<String>,
+}
+
+impl LogicalWindow {
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> LogicalWindow {
+        LogicalWindow {
+            position: Cell::new((x, y)),
+            size: (width, height),
+            visible: Cell::new(false),
+            shape: RefCell::new("none".to_string()),
+        }
+    }
+
+    pub fn set_shape(&self, shape: String) {
+        *self.shape.borrow_mut() = shape;
+    }
+
+    pub fn shape(&self) -> String {
+        self.shape.borrow().clone()
+    }
+
+    pub fn move_(&self, x: i32, y: i32) {
+        self.position.set((x, y));
+    }
+}
+
+impl Surface {
+    pub fn show_all(&self) {
+        match self {
+            Surface::Gtk(w) => w.show_all(),
+            Surface::Logical(w) => w.visible.set(true),
+        }
+    }
+
+    pub fn hide(&self) {
+        match self {
+            Surface::Gtk(w) => w.hide(),
+            Surface::Logical(w) => w.visible.set(false),
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        match self {
+            Surface::Gtk(w) => w.is_visible(),
+            Surface::Logical(w) => w.visible.get(),
+        }
+    }
+
+    pub fn position(&self) -> (i32, i32) {
+        match self {
+            Surface::Gtk(w) => w.position(),
+            Surface::Logical(w) => w.position.get(),
+        }
+    }
+
+    pub fn size(&self) -> (i32, i32) {
+        match self {
+            Surface::Gtk(w) => w.size(),
+            Surface::Logical(w) => w.size,
+        }
+    }
+
+    pub fn move_(&self, x: i32, y: i32) {
+        match self {
+            Surface::Gtk(w) => w.move_(x, y),
+            Surface::Logical(w) => w.move_(x, y),
+        }
+    }
+}
+
+// Swaps the element named sink for a fakesink, so a window pipeline runs in
+// time without a display.
+pub fn replace_sink(pipeline: &gstreamer::Pipeline) -> Result<(), String> {
+    let sink = match pipeline.by_name("sink") {
+        Some(s) => s,
+        None => return Err(format!("Window pipeline {} has no element named sink", pipeline.name())),
+    };
+    swap_for_fakesink(pipeline, &sink)
+}
+
+// Swaps every audio sink for a fakesink, so audio pipelines run in time
+// without a sound card.
+pub fn replace_audio_sinks(pipeline: &gstreamer::Pipeline) -> Result<(), String> {
+    let sinks = pipeline.iterate_sinks().into_iter().flatten().filter(|e| {
+        e.factory().map(|f| f.klass().contains("Sink") && f.klass().contains("Audio")).unwrap_or(false)
+    }).collect::<Vec<gstreamer::Element>>();
+    for sink in sinks {
+        swap_for_fakesink(pipeline, &sink)?;
+    }
+    Ok(())
+}
+
+fn swap_for_fakesink(pipeline: &gstreamer::Pipeline, sink: &gstreamer::Element) -> Result<(), String> {
+    if sink.factory().map(|f| f.name() == "fakesink").unwrap_or(false) {
+        return Ok(());
+    }
+    let peer = sink.static_pad("sink").and_then(|p| p.peer());
+    if let Err(err) = pipeline.remove(sink) {
+        return Err(format!("Could not remove the sink of {}: {}", pipeline.name(), err));
+    }
+    let fake = match gstreamer::ElementFactory::make("fakesink", Some(sink.name().as_str())) {
+        Ok(f) => f,
+        Err(err) => return Err(format!("Could not make a fakesink: {}", err)),
+    };
+    fake.set_property("sync", true);
+    if let Err(err) = pipeline.add(&fake) {
+        return Err(format!("Could not add a fakesink to {}: {}", pipeline.name(), err));
+    }
+    if let Some(peer) = peer {
+        if let Err(err) = peer.link(&fake.static_pad("sink").unwrap()) {
+            return Err(format!("Could not link the fakesink of {}: {:?}", pipeline.name(), err));
+        }
+    }
+    Ok(())
+}
+
+pub fn create_gtk_window(x: i32, y: i32, width: i32, height: i32) -> (gtk::Window, gtk::DrawingArea, u32) {
     let wnd = gtk::Window::new(gtk::WindowType::Toplevel);
     wnd.set_border_width(0);
     wnd.set_decorated(false);
@@ -33,5 +177,17 @@ pub fn create_gtk_window(x: i32, y: i32, width: i32, height: i32) -> (gtk::Windo
     let ndw = dar.window().unwrap();
     let xid = unsafe {gdk_x11_window_get_xid(ndw)};
     println!("Obtained window with xid {}", xid);
-    (wnd, xid)
+    (wnd, dar, xid)
+}
+
+// A small window for operators to focus so hotkeys work without clicking on
+// an output. The label shows the cue stack when the pattern has one.
+pub fn create_control_window() -> (gtk::Window, gtk::Label) {
+    let wnd = gtk::Window::new(gtk::WindowType::Toplevel);
+    wnd.set_title("live-mix");
+    wnd.set_default_size(320, 80);
+    let label = gtk::Label::new(Some("Hotkeys are active while this window has focus"));
+    wnd.add(&label);
+    wnd.show_all();
+    (wnd, label)
 }
@@ -0,0 +1,234 @@
+/*
+ * Copyright 2022 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::sync::Mutex;
+use std::thread;
+
+use crossbeam_channel::{Receiver, Sender};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+use crate::control::{self, ControlRequest};
+
+// Channels to each connected event stream.
+static SUBSCRIBERS: Mutex<Vec<Sender<String>>> = Mutex::new(Vec::new());
+
+// Sends the event to every connected event stream.
+pub fn publish(event: Value) {
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+    if subscribers.is_empty() {
+        return;
+    }
+    let text = event.to_string();
+    subscribers.retain(|s| s.send(text.clone()).is_ok());
+}
+
+// Serves the remote control API on localhost, passing the requests it makes
+// of the show to the returned channel:
+//   GET /pipelines, GET /windows, POST /fire/<mark>, POST /action with an act
+//   line as the body, and GET /events for a WebSocket stream of events.
+pub fn listen(port: u16) -> Result<Receiver<ControlRequest>, String> {
+    let server = match Server::http(("127.0.0.1", port)) {
+        Ok(s) => s,
+        Err(err) => return Err(format!("Could not serve HTTP on port {}: {}", port, err)),
+    };
+    println!("Serving remote control on http://127.0.0.1:{}/", port);
+    let (tx, rx) = crossbeam_channel::unbounded();
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            handle(request, port, &tx);
+        }
+    });
+    Ok(rx)
+}
+
+fn handle(mut request: Request, port: u16, requests: &Sender<ControlRequest>) {
+    if let Err(err) = check_origin(&request, port) {
+        println!("Refused HTTP request: {}", err);
+        let _ = request.respond(Response::from_string(err).with_status_code(403));
+        return;
+    }
+    let url = request.url().to_string();
+    let path = url.split('?').next().unwrap_or("");
+    let result = match (request.method(), path) {
+        (Method::Get, "/events") => return stream_events(request),
+        (Method::Get, "/pipelines") => pipelines(requests),
+        (Method::Get, "/windows") => windows(requests),
+        (Method::Post, p) if p.starts_with("/fire/") => {
+            ask(requests, format!("act mark {}", &p["/fire/".len()..])).map(|_| json!({"ok": true}))
+        },
+        (Method::Get, "/cues") => cues(requests),
+        (Method::Post, "/cue/go") => ask(requests, "act cue go".to_string()).map(|_| json!({"ok": true})),
+        (Method::Post, "/cue/back") => ask(requests, "act cue back".to_string()).map(|_| json!({"ok": true})),
+        (Method::Post, p) if p.starts_with("/cue/jump/") => {
+            ask(requests, format!("act cue jump {}", &p["/cue/jump/".len()..])).map(|_| json!({"ok": true}))
+        },
+        (Method::Post, "/action") => {
+            let mut line = String::new();
+            match request.as_reader().read_to_string(&mut line) {
+                Ok(_) => ask(requests, line.trim().to_string()).map(|reply| json!({"ok": true, "reply": reply})),
+                Err(err) => Err(format!("Could not read request: {}", err)),
+            }
+        },
+        _ => {
+            let _ = request.respond(Response::from_string("Not found").with_status_code(404));
+            return;
+        },
+    };
+    let (status, body) = match result {
+        Ok(body) => (200, body),
+        Err(err) => (400, json!({"ok": false, "error": err})),
+    };
+    let response = Response::from_string(body.to_string())
+        .with_status_code(StatusCode(status))
+        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap());
+    let _ = request.respond(response);
+}
+
+// Binding to localhost doesn't stop a web page in the operator's browser from
+// posting here, or from reaching the port under its own name after DNS
+// rebinding, so only requests addressed to and coming from this port pass.
+fn check_origin(request: &Request, port: u16) -> Result<(), String> {
+    let header = |name: &'static str| request.headers().iter().find(|h| h.field.equiv(name)).map(|h| h.value.as_str().to_string());
+    let hosts = [format!("127.0.0.1:{}", port), format!("localhost:{}", port)];
+    match header("Host") {
+        Some(host) if hosts.contains(&host) => (),
+        Some(host) => return Err(format!("foreign host {}", host)),
+        None => return Err("no host".to_string()),
+    }
+    match header("Origin") {
+        Some(origin) if !hosts.iter().any(|h| origin == format!("http://{}", h)) => Err(format!("foreign origin {}", origin)),
+        _ => Ok(()),
+    }
+}
+
+// Asks the show, splitting the reply from the control socket format.
+fn ask(requests: &Sender<ControlRequest>, line: String) -> Result<String, String> {
+    let reply = control::ask(requests, line).ok_or("The show has stopped")?;
+    match reply.split_once(' ') {
+        Some(("ok", rest)) => Ok(rest.to_string()),
+        Some(("error", err)) => Err(err.to_string()),
+        _ if reply == "ok" => Ok("".to_string()),
+        _ => Err(reply),
+    }
+}
+
+fn pipelines(requests: &Sender<ControlRequest>) -> Result<Value, String> {
+    let names = ask(requests, "query pipes".to_string())?;
+    let mut pipelines = Vec::new();
+    for name in names.split_whitespace() {
+        let state = ask(requests, format!("query state {}", name))?;
+        let position = ask(requests, format!("query position {}", name)).ok().and_then(|p| p.parse::<f64>().ok());
+        pipelines.push(json!({"name": name, "state": state, "position": position}));
+    }
+    Ok(Value::Array(pipelines))
+}
+
+fn cues(requests: &Sender<ControlRequest>) -> Result<Value, String> {
+    let names = ask(requests, "query cues".to_string())?;
+    let position = ask(requests, "query cue".to_string())?;
+    let (current, standby) = position.split_once(' ').ok_or(format!("Unexpected cue reply: {}", position))?;
+    let name = |n: &str| if n == "-" { Value::Null } else { json!(n) };
+    Ok(json!({
+        "cues": names.split_whitespace().collect::<Vec<&str>>(),
+        "current": name(current),
+        "standby": name(standby),
+    }))
+}
+
+fn windows(requests: &Sender<ControlRequest>) -> Result<Value, String> {
+    let names = ask(requests, "query windows".to_string())?;
+    let mut windows = Vec::new();
+    for name in names.split_whitespace() {
+        let window = ask(requests, format!("query window {}", name))?;
+        windows.push(window_json(name, &window)?);
+    }
+    Ok(Value::Array(windows))
+}
+
+// Reads a query window reply: position, size and visibility, then the shape
+// of a headless window.
+fn window_json(name: &str, reply: &str) -> Result<Value, String> {
+    let fields = reply.split_whitespace().collect::<Vec<&str>>();
+    if fields.len() < 5 {
+        return Err(format!("Unexpected window reply: {}", reply));
+    }
+    let number = |s: &str| s.parse::<i64>().unwrap_or(0);
+    let shape = if fields.len() > 5 { json!(fields[5..].join(" ")) } else { Value::Null };
+    Ok(json!({
+        "name": name,
+        "x": number(fields[0]),
+        "y": number(fields[1]),
+        "width": number(fields[2]),
+        "height": number(fields[3]),
+        "visible": fields[4] == "true",
+        "shape": shape,
+    }))
+}
+
+fn stream_events(request: Request) {
+    let key = request.headers().iter()
+        .find(|h| h.field.equiv("Sec-WebSocket-Key"))
+        .map(|h| h.value.to_string());
+    let key = match key {
+        Some(k) => k,
+        None => {
+            let _ = request.respond(Response::from_string("Expected a WebSocket upgrade").with_status_code(400));
+            return;
+        }
+    };
+    let response = Response::empty(101)
+        .with_header(Header::from_bytes("Upgrade", "websocket").unwrap())
+        .with_header(Header::from_bytes("Sec-WebSocket-Accept", tungstenite::handshake::derive_accept_key(key.as_bytes())).unwrap());
+    let stream = request.upgrade("websocket", response);
+    let (tx, rx) = crossbeam_channel::unbounded();
+    SUBSCRIBERS.lock().unwrap().push(tx);
+    thread::spawn(move || {
+        let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+        for event in rx {
+            if socket.write_message(Message::Text(event)).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_without_shape() {
+        let window = window_json("out", "10 20 640 480 true").unwrap();
+        assert_eq!(window, json!({
+            "name": "out", "x": 10, "y": 20, "width": 640, "height": 480, "visible": true, "shape": null,
+        }));
+    }
+
+    #[test]
+    fn window_with_shape() {
+        let window = window_json("out", "0 0 320 240 false polygon 0 0 1 0 0.5 1").unwrap();
+        assert_eq!(window["visible"], json!(false));
+        assert_eq!(window["shape"], json!("polygon 0 0 1 0 0.5 1"));
+    }
+
+    #[test]
+    fn short_window_reply() {
+        assert!(window_json("out", "0 0 320").is_err());
+    }
+}
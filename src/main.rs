@@ -14,29 +14,58 @@
  * limitations under the License.
 */
 
+use std::ffi::OsString;
+
 use gtk::prelude::{ApplicationExt, ApplicationExtManual};
 
+use crate::gtk_manage::options::Options;
+
 mod script;
 mod gtk_manage;
 mod error;
+mod analyze;
+mod control;
+mod http;
+mod midi;
+mod osc;
+mod repl;
 
 fn main() -> Result<(), i32> {
     if gstreamer::init().is_err() {
         println!("Could not initialize gstreamer.");
         std::process::exit(1);
     }
-    if gtk::init().is_err() {
-        println!("Could not initialize gtk.");
-        std::process::exit(1);
+    let args = std::env::args().collect::<Vec<String>>();
+    if args.len() > 1 && args[1] == "analyze" {
+        let ret = analyze::run(&args[2..]);
+        return if ret == 0 { Ok(()) } else { Err(ret) };
     }
+    let options = match Options::parse(&std::env::args_os().collect::<Vec<OsString>>()) {
+        Ok(o) => o,
+        Err(err) => {
+            println!("{}", err);
+            return Err(1);
+        }
+    };
+    // headless runs only need the main loop, not a display
+    let ret = if options.headless {
+        let app = gtk::gio::Application::new(Some("dev.subsy.live-mix"), gtk::gio::ApplicationFlags::HANDLES_COMMAND_LINE);
+        app.connect_command_line(move |app, _| gtk_manage::run_app(app, &options));
+        app.run()
+    } else {
+        if gtk::init().is_err() {
+            println!("Could not initialize gtk.");
+            std::process::exit(1);
+        }
 
-    let app = gtk::Application::builder()
-        .application_id("dev.subsy.live-mix")
-        .build();
-    app.set_flags(gtk::gio::ApplicationFlags::HANDLES_COMMAND_LINE);
+        let app = gtk::Application::builder()
+            .application_id("dev.subsy.live-mix")
+            .build();
+        app.set_flags(gtk::gio::ApplicationFlags::HANDLES_COMMAND_LINE);
 
-    app.connect_command_line(gtk_manage::run_app);
-    let ret = app.run();
+        app.connect_command_line(move |app, _| gtk_manage::run_app(app, &options));
+        app.run()
+    };
     if ret == 0 {
         Ok(())
     } else {
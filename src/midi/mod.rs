@@ -0,0 +1,81 @@
+/*
+ * Copyright 2022 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::ffi::CString;
+use std::thread;
+
+use alsa::seq::{EvCtrl, EvNote, EventType, PortCap, PortType, Seq};
+use crossbeam_channel::Receiver;
+
+// Channels are numbered from 1, as on controllers.
+#[derive(Debug, Clone)]
+pub enum MidiEvent {
+    Note { channel: u8, note: u8, velocity: u8 },
+    Control { channel: u8, param: u32, value: i32 },
+}
+
+// Creates an ALSA sequencer client with a writable port for controllers to
+// be connected to, e.g. with aconnect, passing note on and control change
+// events to the returned channel.
+pub fn listen(name: &str) -> Result<Receiver<MidiEvent>, String> {
+    let seq = match Seq::open(None, Some(alsa::Direction::Capture), false) {
+        Ok(s) => s,
+        Err(err) => return Err(format!("Could not open ALSA sequencer: {}", err)),
+    };
+    let client_name = CString::new(name).unwrap();
+    if let Err(err) = seq.set_client_name(&client_name) {
+        return Err(format!("Could not name ALSA sequencer client: {}", err));
+    }
+    let port_name = CString::new("input").unwrap();
+    let port = match seq.create_simple_port(&port_name, PortCap::WRITE | PortCap::SUBS_WRITE, PortType::MIDI_GENERIC | PortType::APPLICATION) {
+        Ok(p) => p,
+        Err(err) => return Err(format!("Could not create ALSA sequencer port: {}", err)),
+    };
+    match seq.client_id() {
+        Ok(id) => println!("Listening for MIDI on sequencer port {}:{}", id, port),
+        Err(_) => println!("Listening for MIDI on sequencer port {}", name),
+    }
+
+    let (tx, rx) = crossbeam_channel::unbounded();
+    thread::spawn(move || {
+        let mut input = seq.input();
+        loop {
+            let event = match input.event_input() {
+                Ok(e) => e,
+                Err(err) => {
+                    println!("MIDI receive failed: {}", err);
+                    continue;
+                }
+            };
+            let midi = match event.get_type() {
+                EventType::Noteon => match event.get_data::<EvNote>() {
+                    // note on with no velocity is a note off
+                    Some(n) if n.velocity > 0 => MidiEvent::Note { channel: n.channel + 1, note: n.note, velocity: n.velocity },
+                    _ => continue,
+                },
+                EventType::Controller => match event.get_data::<EvCtrl>() {
+                    Some(c) => MidiEvent::Control { channel: c.channel + 1, param: c.param, value: c.value },
+                    None => continue,
+                },
+                _ => continue,
+            };
+            if tx.send(midi).is_err() {
+                return;
+            }
+        }
+    });
+    Ok(rx)
+}
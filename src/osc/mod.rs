@@ -0,0 +1,319 @@
+/*
+ * Copyright 2022 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::thread;
+
+use crossbeam_channel::Receiver;
+
+// Largest datagram read from the socket.
+const MAX_PACKET: usize = 65536;
+
+#[derive(Debug, Clone)]
+pub enum OscValue {
+    Int(i64),
+    Float(f64),
+    String(String),
+    Bool(bool),
+    Nil,
+}
+
+impl std::fmt::Display for OscValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OscValue::Int(i) => write!(f, "{}", i),
+            OscValue::Float(v) => write!(f, "{}", v),
+            OscValue::String(s) => write!(f, "{}", s),
+            OscValue::Bool(b) => write!(f, "{}", if *b { 1 } else { 0 }),
+            OscValue::Nil => write!(f, "0"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<OscValue>,
+}
+
+// Listens for OSC packets on the UDP port, passing each message, including
+// those inside bundles, to the returned channel. Only local senders can reach
+// the port unless lan is set.
+pub fn listen(port: u16, lan: bool) -> Result<Receiver<OscMessage>, String> {
+    let host = if lan { "0.0.0.0" } else { "127.0.0.1" };
+    let socket = match UdpSocket::bind((host, port)) {
+        Ok(s) => s,
+        Err(err) => return Err(format!("Could not listen for OSC on port {}: {}", port, err)),
+    };
+    println!("Listening for OSC on {}:{}", host, port);
+    let (tx, rx) = crossbeam_channel::unbounded();
+    thread::spawn(move || {
+        let mut buf = vec![0; MAX_PACKET];
+        loop {
+            let (len, from) = match socket.recv_from(&mut buf) {
+                Ok(r) => r,
+                Err(err) => {
+                    println!("OSC receive failed: {}", err);
+                    continue;
+                }
+            };
+            let mut messages = Vec::new();
+            if let Err(err) = decode_packet(&buf[..len], &mut messages) {
+                println!("Bad OSC packet from {}: {}", from, err);
+                continue;
+            }
+            for message in messages {
+                if tx.send(message).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    Ok(rx)
+}
+
+// Sends prepared messages without blocking the caller; a full socket buffer
+// drops the message.
+pub struct OscSender {
+    socket: UdpSocket,
+    target: SocketAddr,
+}
+
+impl OscSender {
+    pub fn new(target: &str) -> Result<OscSender, String> {
+        let target = match target.to_socket_addrs().map(|mut a| a.next()) {
+            Ok(Some(a)) => a,
+            _ => return Err(format!("Could not resolve OSC target {}", target)),
+        };
+        let local = if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = match UdpSocket::bind(local) {
+            Ok(s) => s,
+            Err(err) => return Err(format!("Could not open OSC socket: {}", err)),
+        };
+        if let Err(err) = socket.set_nonblocking(true) {
+            return Err(format!("Could not open OSC socket: {}", err));
+        }
+        Ok(OscSender { socket, target })
+    }
+
+    pub fn send(&self, packet: &[u8]) {
+        if let Err(err) = self.socket.send_to(packet, self.target) {
+            println!("Could not send OSC to {}: {}", self.target, err);
+        }
+    }
+}
+
+// Guesses argument types from the text: integers, then floats, then strings.
+pub fn parse_value(s: &str) -> OscValue {
+    if let Ok(i) = s.parse::<i32>() {
+        OscValue::Int(i as i64)
+    } else if let Ok(f) = s.parse::<f32>() {
+        OscValue::Float(f as f64)
+    } else {
+        OscValue::String(s.to_string())
+    }
+}
+
+pub fn encode_message(address: &str, args: &[OscValue]) -> Vec<u8> {
+    let mut tags = ",".to_string();
+    let mut data = Vec::new();
+    for arg in args {
+        match arg {
+            OscValue::Int(i) if *i as i32 as i64 == *i => {
+                tags.push('i');
+                data.extend_from_slice(&(*i as i32).to_be_bytes());
+            },
+            OscValue::Int(i) => {
+                tags.push('h');
+                data.extend_from_slice(&i.to_be_bytes());
+            },
+            OscValue::Float(f) => {
+                tags.push('f');
+                data.extend_from_slice(&(*f as f32).to_be_bytes());
+            },
+            OscValue::String(s) => {
+                tags.push('s');
+                write_string(&mut data, s);
+            },
+            OscValue::Bool(true) => tags.push('T'),
+            OscValue::Bool(false) => tags.push('F'),
+            OscValue::Nil => tags.push('N'),
+        }
+    }
+    let mut packet = Vec::new();
+    write_string(&mut packet, address);
+    write_string(&mut packet, tags.as_str());
+    packet.extend(data);
+    packet
+}
+
+fn write_string(data: &mut Vec<u8>, s: &str) {
+    data.extend_from_slice(s.as_bytes());
+    let padding = 4 - s.len() % 4;
+    data.resize(data.len() + padding, 0);
+}
+
+// Decodes a message or bundle. Bundle time tags are ignored and their
+// messages handled straight away.
+pub fn decode_packet(data: &[u8], messages: &mut Vec<OscMessage>) -> Result<(), String> {
+    if !data.starts_with(b"#bundle\0") {
+        messages.push(decode_message(data)?);
+        return Ok(());
+    }
+    let mut at = 16;
+    while at < data.len() {
+        let size = read_size(data, &mut at)?;
+        let end = at.checked_add(size).filter(|e| *e <= data.len()).ok_or("bundle element overruns packet")?;
+        decode_packet(&data[at..end], messages)?;
+        at = end;
+    }
+    Ok(())
+}
+
+fn decode_message(data: &[u8]) -> Result<OscMessage, String> {
+    let mut at = 0;
+    let address = read_string(data, &mut at)?;
+    if !address.starts_with('/') {
+        return Err(format!("bad address: {}", address));
+    }
+    // old senders may leave out the type tags
+    if at >= data.len() {
+        return Ok(OscMessage { address, args: Vec::new() });
+    }
+    let tags = read_string(data, &mut at)?;
+    let tags = tags.strip_prefix(',').ok_or("missing type tags")?;
+    let mut args = Vec::new();
+    for tag in tags.chars() {
+        let value = match tag {
+            'i' => OscValue::Int(read_i32(data, &mut at)? as i64),
+            'h' => OscValue::Int(i64::from_be_bytes(read_bytes(data, &mut at)?)),
+            'f' => OscValue::Float(f32::from_be_bytes(read_bytes(data, &mut at)?) as f64),
+            'd' => OscValue::Float(f64::from_be_bytes(read_bytes(data, &mut at)?)),
+            's' | 'S' => OscValue::String(read_string(data, &mut at)?),
+            'b' => {
+                let size = read_size(data, &mut at)?;
+                at = size.checked_next_multiple_of(4)
+                    .and_then(|s| at.checked_add(s))
+                    .filter(|end| *end <= data.len())
+                    .ok_or("blob overruns packet")?;
+                OscValue::Nil
+            },
+            'T' => OscValue::Bool(true),
+            'F' => OscValue::Bool(false),
+            'N' | 'I' => OscValue::Nil,
+            t => return Err(format!("unsupported type tag: {}", t)),
+        };
+        args.push(value);
+    }
+    Ok(OscMessage { address, args })
+}
+
+fn read_bytes<const N: usize>(data: &[u8], at: &mut usize) -> Result<[u8; N], String> {
+    let bytes = at.checked_add(N).and_then(|end| data.get(*at..end)).ok_or("argument overruns packet")?;
+    *at += N;
+    Ok(bytes.try_into().unwrap())
+}
+
+fn read_i32(data: &[u8], at: &mut usize) -> Result<i32, String> {
+    Ok(i32::from_be_bytes(read_bytes(data, at)?))
+}
+
+// Reads a blob or bundle element size, which can't be negative.
+fn read_size(data: &[u8], at: &mut usize) -> Result<usize, String> {
+    let size = read_i32(data, at)?;
+    usize::try_from(size).map_err(|_| format!("negative size: {}", size))
+}
+
+// Reads a null terminated string padded to four bytes.
+fn read_string(data: &[u8], at: &mut usize) -> Result<String, String> {
+    let rest = data.get(*at..).ok_or("string overruns packet")?;
+    let len = rest.iter().position(|b| *b == 0).ok_or("unterminated string")?;
+    let s = String::from_utf8_lossy(&rest[..len]).to_string();
+    *at += (len + 4) / 4 * 4;
+    Ok(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(data: &[u8]) -> Result<Vec<OscMessage>, String> {
+        let mut messages = Vec::new();
+        decode_packet(data, &mut messages)?;
+        Ok(messages)
+    }
+
+    #[test]
+    fn round_trips_arguments() {
+        let args = [OscValue::Int(3), OscValue::Int(1 << 40), OscValue::Float(0.5), OscValue::String("abc".to_string()), OscValue::Bool(true), OscValue::Nil];
+        let messages = decode(&encode_message("/mix/fader", &args)).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].address, "/mix/fader");
+        let values = messages[0].args.iter().map(|a| a.to_string()).collect::<Vec<String>>();
+        assert_eq!(values, ["3", "1099511627776", "0.5", "abc", "1", "0"]);
+    }
+
+    #[test]
+    fn decodes_bundles() {
+        let message = encode_message("/a", &[OscValue::Int(1)]);
+        let mut bundle = b"#bundle\0".to_vec();
+        bundle.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+        for _ in 0..2 {
+            bundle.extend_from_slice(&(message.len() as i32).to_be_bytes());
+            bundle.extend_from_slice(&message);
+        }
+        let messages = decode(&bundle).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].address, "/a");
+    }
+
+    #[test]
+    fn skips_blobs() {
+        let mut packet = Vec::new();
+        write_string(&mut packet, "/b");
+        write_string(&mut packet, ",bi");
+        packet.extend_from_slice(&5i32.to_be_bytes());
+        packet.extend_from_slice(&[1, 2, 3, 4, 5, 0, 0, 0]);
+        packet.extend_from_slice(&7i32.to_be_bytes());
+        let messages = decode(&packet).unwrap();
+        assert_eq!(messages[0].args[1].to_string(), "7");
+    }
+
+    #[test]
+    fn rejects_bad_sizes() {
+        for size in [-1, i32::MIN, i32::MAX, 16] {
+            let mut packet = Vec::new();
+            write_string(&mut packet, "/b");
+            write_string(&mut packet, ",b");
+            packet.extend_from_slice(&size.to_be_bytes());
+            assert!(decode(&packet).is_err(), "blob size {}", size);
+
+            let mut bundle = b"#bundle\0".to_vec();
+            bundle.extend_from_slice(&[0; 8]);
+            bundle.extend_from_slice(&size.to_be_bytes());
+            assert!(decode(&bundle).is_err(), "bundle element size {}", size);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_packets() {
+        let packet = encode_message("/a", &[OscValue::Int(1), OscValue::String("long string".to_string())]);
+        for len in 0..packet.len() {
+            let _ = decode(&packet[..len]);
+        }
+        assert!(decode(b"no slash\0\0\0\0").is_err());
+    }
+}
@@ -0,0 +1,205 @@
+/*
+ * Copyright 2022 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crossbeam_channel::Receiver;
+use gstreamer::prelude::{GstBinExtManual, GstObjectExt, ObjectExt};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+use crate::control::{self, ControlRequest};
+use crate::script::Pattern;
+
+static COMMANDS: &[&str] = &["raw", "new", "plug", "layout", "tempo", "on", "act", "query", "cue", "scene"];
+static TRIGGERS: &[&str] = &[
+    "callback", "level", "key", "click", "hover", "signal", "osc", "midi",
+    "mark", "after", "at", "every", "pre", "progress",
+];
+static ACTIONS: &[&str] = &["prop", "play", "seek", "fallback", "window"];
+static STATES: &[&str] = &["start", "pause", "ready", "null", "restart"];
+static TYPES: &[&str] = &["int", "float", "string", "GstOrientation"];
+static QUERIES: &[&str] = &["pipes", "windows", "position", "state", "window"];
+
+// Names the console completes, refreshed on the gtk thread whenever the
+// pattern changes.
+#[derive(Default)]
+pub struct Completions {
+    templates: Vec<String>,
+    // pipeline -> element -> properties
+    pipes: BTreeMap<String, BTreeMap<String, Vec<String>>>,
+}
+
+impl Completions {
+    pub fn new(pattern: &Pattern) -> Completions {
+        let mut pipes = BTreeMap::new();
+        for (name, (pipeline, _)) in &pattern.pipes {
+            let mut elements = BTreeMap::new();
+            for element in pipeline.iterate_recurse().into_iter().flatten() {
+                let props = element.list_properties().iter().map(|p| p.name().to_string()).collect();
+                elements.insert(element.name().to_string(), props);
+            }
+            pipes.insert(name.clone(), elements);
+        }
+        let mut templates = pattern.blocks.keys().cloned().collect::<Vec<String>>();
+        templates.sort();
+        Completions { templates, pipes }
+    }
+
+    fn candidates(&self, words: &[&str]) -> Vec<String> {
+        // actions can follow a trigger, so complete from the last act
+        if let Some(at) = words.iter().rposition(|w| *w == "act") {
+            if at > 0 {
+                return self.candidates(&words[at..]);
+            }
+        }
+        let names = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        let pipes = || self.pipes.keys().cloned().collect::<Vec<String>>();
+        let elements = |pipe: &str| self.pipes.get(pipe).map(|e| e.keys().cloned().collect()).unwrap_or_default();
+        match (words.first(), words.len()) {
+            (None, _) => names(COMMANDS),
+            (Some(&"act"), 1) => {
+                let mut c = pipes();
+                c.extend(names(&["mark", "osc", "cue", "scene"]));
+                c
+            },
+            (Some(&"act"), 2) => names(ACTIONS),
+            (Some(&"act"), 3) => match words[2] {
+                "prop" | "fallback" => elements(words[1]),
+                "play" => names(STATES),
+                _ => Vec::new(),
+            },
+            (Some(&"act"), 4) if words[2] == "prop" => {
+                self.pipes.get(words[1]).and_then(|e| e.get(words[3])).cloned().unwrap_or_default()
+            },
+            (Some(&"act"), 5) if words[2] == "prop" => names(TYPES),
+            (Some(&"new"), 1) => self.templates.clone(),
+            (Some(&"plug"), 1) | (Some(&"plug"), 3) => {
+                let mut c = self.pipes.values().flat_map(|e| e.keys().cloned()).collect::<Vec<String>>();
+                c.sort();
+                c.dedup();
+                c
+            },
+            (Some(&"plug"), 2) | (Some(&"plug"), 4) => pipes(),
+            (Some(&"on"), 1) => names(TRIGGERS),
+            (Some(&"on"), 2) if ["callback", "level", "progress", "click", "hover"].contains(&words[1]) => pipes(),
+            (Some(&"on"), 3) if words[1] == "level" => elements(words[2]),
+            (Some(&"query"), 1) => names(QUERIES),
+            (Some(&"query"), 2) => pipes(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+struct PatternHelper {
+    completions: Arc<Mutex<Completions>>,
+}
+
+impl Completer for PatternHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos].rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+        let words = line[..start].split_whitespace().collect::<Vec<&str>>();
+        let prefix = &line[start..pos];
+        let candidates = self.completions.lock().unwrap().candidates(&words);
+        Ok((start, candidates.into_iter().filter(|c| c.starts_with(prefix)).collect()))
+    }
+}
+
+impl Hinter for PatternHelper {
+    type Hint = String;
+}
+
+impl Highlighter for PatternHelper {}
+
+impl Validator for PatternHelper {}
+
+impl Helper for PatternHelper {}
+
+// Reads pattern lines from stdin, passing each to the returned channel and
+// printing anything but a plain ok reply. Blocks opened with raw or wrap are
+// read up to their war or parw, and cue and scene blocks up to their end, and
+// sent whole.
+pub fn start(completions: Arc<Mutex<Completions>>) -> Receiver<ControlRequest> {
+    let (tx, rx) = crossbeam_channel::unbounded();
+    thread::spawn(move || {
+        let mut editor = Editor::<PatternHelper>::new();
+        editor.set_helper(Some(PatternHelper { completions }));
+        let mut block = String::new();
+        let mut end = None;
+        loop {
+            let prompt = if end.is_some() { "...> " } else { "live-mix> " };
+            let line = match editor.readline(prompt) {
+                Ok(l) => l,
+                Err(ReadlineError::Interrupted) => {
+                    block.clear();
+                    end = None;
+                    continue;
+                },
+                Err(ReadlineError::Eof) => {
+                    println!("Console closed");
+                    return;
+                },
+                Err(err) => {
+                    println!("Console error: {}", err);
+                    return;
+                },
+            };
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with("//") {
+                continue;
+            }
+            editor.add_history_entry(trimmed);
+            block.push_str(trimmed);
+            block.push('\n');
+            let first = trimmed.split_whitespace().next();
+            match end {
+                Some("end") if first == Some("end") => end = None,
+                Some("end") => continue,
+                Some(e) if trimmed.starts_with(e) => end = None,
+                Some(_) => continue,
+                None if first == Some("cue") || first == Some("scene") => {
+                    end = Some("end");
+                    continue;
+                },
+                None if trimmed.starts_with("raw") => {
+                    end = Some("war");
+                    continue;
+                },
+                None if trimmed.split_whitespace().any(|w| w == "wrap") => {
+                    end = Some("parw");
+                    continue;
+                },
+                None => (),
+            }
+            let reply = match control::ask(&tx, std::mem::take(&mut block)) {
+                Some(r) => r,
+                None => return,
+            };
+            if reply != "ok" {
+                println!("{}", reply);
+            }
+        }
+    });
+    rx
+}
@@ -16,15 +16,26 @@
 
 use gstreamer::ClockTime;
 use gstreamer::prelude::*;
-use crate::catch_bail;
+use crate::{catch_bail, catch_bail_annotate, none_bail};
 
 use crate::error::{ParseError, ParseResult};
+use crate::gtk_manage::shape::Shape;
+use crate::http;
+use crate::osc::{self, OscSender};
 use crate::script::Pattern;
 
 pub trait EventAction: Send + Sync {
     fn exec(&self);
 }
 
+// Runs a trigger's actions, telling remote event streams it fired.
+pub fn fire(trigger: &str, events: &[Box<dyn EventAction>]) {
+    http::publish(serde_json::json!({"type": "trigger", "trigger": trigger}));
+    for event in events {
+        event.exec();
+    }
+}
+
 pub struct WindowAction {
     window: String,
     action: String,
@@ -75,6 +86,13 @@ impl EventAction for PlayAction {
             "null" => {
                 gstreamer::State::Null
             },
+            // back to the start, e.g. to recover from an error
+            "restart" => {
+                if let Err(err) = pipeline.set_state(gstreamer::State::Null) {
+                    println!("PlayAction state change error: {:?}", err)
+                }
+                gstreamer::State::Playing
+            },
             a => panic!("Unknown pipeline state: {}", a)
         };
         match pipeline.set_state(action) {
@@ -120,11 +138,182 @@ impl EventAction for SetPropAction {
     fn exec(&self) {
         let status = set_property(self.element.clone(), self.prop.clone(), self.type_.clone(), self.setting.clone());
         if let Err(err) = status {
-            panic!("{}",err.annotate("set_property"));
+            println!("Error setting {}: {}", self.prop, err.annotate("set_property"));
+        }
+    }
+}
+
+pub struct OscSendAction {
+    sender: OscSender,
+    packet: Vec<u8>,
+}
+
+impl EventAction for OscSendAction {
+    fn exec(&self) {
+        self.sender.send(&self.packet);
+    }
+}
+
+pub struct FallbackAction {
+    pipeline: gstreamer::Pipeline,
+    element: gstreamer::Element,
+    location: String,
+}
+
+unsafe impl Send for FallbackAction {}
+unsafe impl Sync for FallbackAction {}
+
+impl EventAction for FallbackAction {
+    fn exec(&self) {
+        println!("Switching {} to fallback {}", self.pipeline.name(), self.location);
+        if let Err(err) = self.pipeline.set_state(gstreamer::State::Null) {
+            println!("FallbackAction state change error: {:?}", err);
+        }
+        self.element.set_property("location", self.location.clone());
+        if let Err(err) = self.pipeline.set_state(gstreamer::State::Playing) {
+            println!("FallbackAction state change error: {:?}", err);
+        }
+    }
+}
+
+// Converts an incoming value, e.g. an OSC argument, for use in an action.
+// With a range, the value is taken as a fraction of it.
+pub struct ValueMapping {
+    pub type_: String,
+    pub range: Option<(f64, f64)>,
+}
+
+impl ValueMapping {
+    pub fn parse(args: &[&str]) -> ParseResult<ValueMapping> {
+        let type_ = match args.first() {
+            Some(t) if ["int", "float", "string"].contains(t) => t.to_string(),
+            t => return Err(ParseError::report_string(format!("Expected int, float or string, got {:?}", t))),
+        };
+        let range = match args.len() {
+            1 => None,
+            3 => {
+                let min = catch_bail!(args[1].parse::<f64>(), format!("Could not parse minimum: {}", args[1]));
+                let max = catch_bail!(args[2].parse::<f64>(), format!("Could not parse maximum: {}", args[2]));
+                Some((min, max))
+            },
+            _ => return Err(ParseError::report("A value mapping is a type and an optional minimum and maximum")),
+        };
+        Ok(ValueMapping { type_, range })
+    }
+
+    pub fn map(&self, value: &str) -> Result<String, String> {
+        if self.type_ == "string" {
+            return Ok(value.to_string());
+        }
+        let mut number = catch_bail!(value.parse::<f64>(), format!("Expected a number, got {}", value));
+        if let Some((min, max)) = self.range {
+            number = min + number.clamp(0.0, 1.0) * (max - min);
+        }
+        if self.type_ == "int" {
+            Ok(format!("{}", number.round() as i64))
+        } else {
+            Ok(format!("{}", number))
+        }
+    }
+}
+
+// An act line parsed when its trigger fires, with $1, $2... replaced by the
+// trigger's values. Lines written with a mapping take the first value through
+// it and append the result.
+pub struct ActionTemplate {
+    words: Vec<String>,
+    mapping: Option<ValueMapping>,
+}
+
+impl ActionTemplate {
+    // Parses either `[type [min max]] -> <act line>` or an act line with $n.
+    pub fn parse(pattern: &Pattern, args: &[&str]) -> ParseResult<ActionTemplate> {
+        let template = match args.iter().position(|a| *a == "->") {
+            Some(arrow) => {
+                // values pass through as floats by default
+                let mapping = if arrow == 0 { ValueMapping::parse(&["float"])? } else { ValueMapping::parse(&args[..arrow])? };
+                let mut words = args[arrow + 1..].iter().map(|s| s.to_string()).collect::<Vec<String>>();
+                // act <pipe> prop <elem> <prop> takes the mapping's type too
+                if words.len() == 5 && words[2] == "prop" && mapping.type_ != "string" {
+                    words.push(mapping.type_.clone());
+                }
+                words.push("$1".to_string());
+                ActionTemplate { words, mapping: Some(mapping) }
+            },
+            None => ActionTemplate { words: args.iter().map(|s| s.to_string()).collect(), mapping: None },
+        };
+        if template.words.first().map(|w| w.as_str()) != Some("act") {
+            return Err(ParseError::report("Actions taking values must be a single act line"));
         }
+        // check the line parses, with zeroes standing in for the values
+        let zeroes = vec!["0".to_string(); 9];
+        let words = catch_bail!(template.render(&zeroes), "Could not substitute values");
+        let (tx, _) = crossbeam_channel::unbounded();
+        parse_single_event(pattern, tx, &words.iter().map(|s| s.as_str()).collect::<Vec<&str>>())?;
+        Ok(template)
+    }
+
+    pub fn uses_values(args: &[&str]) -> bool {
+        args.iter().any(|a| *a == "->" || a.starts_with('$'))
+    }
+
+    fn render(&self, values: &[String]) -> Result<Vec<String>, String> {
+        let mut words = Vec::new();
+        for word in &self.words {
+            let index = match word.strip_prefix('$').map(|i| i.parse::<usize>()) {
+                Some(Ok(i)) if i > 0 => i,
+                _ => {
+                    words.push(word.clone());
+                    continue;
+                },
+            };
+            let value = none_bail!(values.get(index - 1), format!("No value ${}", index));
+            match &self.mapping {
+                Some(mapping) => words.push(mapping.map(value)?),
+                None => words.push(value.clone()),
+            }
+        }
+        Ok(words)
+    }
+
+    pub fn exec(&self, pattern: &Pattern, values: &[String]) -> Result<(), String> {
+        let words = self.render(values)?;
+        let chan = none_bail!(pattern.send.clone(), "Pattern has no channel");
+        let event = match parse_single_event(pattern, chan, &words.iter().map(|s| s.as_str()).collect::<Vec<&str>>()) {
+            Ok(e) => e,
+            Err(err) => return Err(err.to_string()),
+        };
+        event.exec();
+        Ok(())
     }
 }
 
+// Checks that the element has the property, that it holds the given type and
+// that the value parses as it, so setting it later can't panic.
+pub fn check_property(element: &gstreamer::Element, prop: &str, type_: &str, value: &str) -> ParseResult<()> {
+    let spec = none_bail!(element.find_property(prop), format!("Element {} has no property {}", element.name(), prop));
+    let expected = match type_ {
+        "int" => i32::static_type(),
+        "float" => f64::static_type(),
+        "string" => String::static_type(),
+        "GstOrientation" => gstreamer_video::VideoOrientationMethod::static_type(),
+        _ => return Err(ParseError::report_string(format!("Unknown parameter type: {}", type_))),
+    };
+    if !expected.is_a(spec.value_type()) {
+        return Err(ParseError::report_string(format!("Property {} holds {}, not {}", prop, spec.value_type(), type_)));
+    }
+    let parses = match type_ {
+        "int" => value.parse::<i32>().is_ok(),
+        "float" => value.parse::<f64>().is_ok(),
+        "GstOrientation" => ["0", "1", "2", "3"].contains(&value),
+        _ => true,
+    };
+    if !parses {
+        return Err(ParseError::report_string(format!("Unable to parse {} as {}", value, type_)));
+    }
+    Ok(())
+}
+
 pub fn set_property(element: gstreamer::Element, prop: String, type_: String, value: String) -> ParseResult<()> {
     match type_.as_str() {
         "int" => {
@@ -159,7 +348,8 @@ pub fn set_property(element: gstreamer::Element, prop: String, type_: String, va
 pub fn parse_leading_event<'a, I>(pattern: &mut Pattern, chan: crossbeam_channel::Sender<String>, args: &[&str], cmd_iter: &mut I) -> ParseResult<Vec<Box<dyn EventAction>>>
     where I: Iterator<Item = &'a str>, {
     let mut actions = Vec::new();
-    match args[0] {
+    // triggers typed at the console can end before their actions
+    match *none_bail!(args.first(), "Expected actions") {
         "terminate" => {
             actions.push(
                 Box::new(WindowAction {
@@ -178,9 +368,9 @@ pub fn parse_leading_event<'a, I>(pattern: &mut Pattern, chan: crossbeam_channel
         },
         "wrap" => {
             loop {
-                let line = cmd_iter.next().unwrap();
+                let line = none_bail!(cmd_iter.next(), "wrap ended without parw");
                 let args: Vec<&str> = line.split_whitespace().collect();
-                if args[0] == "parw" {
+                if args.first() == Some(&"parw") {
                     break;
                 }
 
@@ -196,9 +386,75 @@ pub fn parse_leading_event<'a, I>(pattern: &mut Pattern, chan: crossbeam_channel
     Ok(actions)
 }
 
-fn parse_single_event(pattern: &Pattern, chan: crossbeam_channel::Sender<String>, args: &[&str]) -> ParseResult<Box<dyn EventAction>> {
-    let result = match args[0] {
+pub fn parse_single_event(pattern: &Pattern, chan: crossbeam_channel::Sender<String>, args: &[&str]) -> ParseResult<Box<dyn EventAction>> {
+    let result = match args.first().copied().unwrap_or_default() {
+        // marks are handled on the gtk thread, like window actions
+        "act" if args.get(1) == Some(&"mark") => {
+            if args.len() != 3 {
+                return Err(ParseError::report("mark expects a name"));
+            }
+            Box::new(WindowAction {
+                window: "".to_string(),
+                action: "mark".to_string(),
+                settings: vec![args[2].to_string()],
+                chan,
+            }) as Box<dyn EventAction>
+        },
+        "act" if args.get(1) == Some(&"cue") => {
+            match args[2..] {
+                ["go"] | ["back"] | ["jump", _] => (),
+                _ => return Err(ParseError::report("cue expects go, back or jump <name>")),
+            }
+            Box::new(WindowAction {
+                window: "".to_string(),
+                action: "cue".to_string(),
+                settings: args[2..].iter().map(|s| s.to_string()).collect(),
+                chan,
+            }) as Box<dyn EventAction>
+        },
+        "act" if args.get(1) == Some(&"scene") => {
+            match args[2..] {
+                [_] | ["snapshot", _] => (),
+                [_, "fade", seconds] => {
+                    catch_bail!(seconds.parse::<f64>(), format!("Could not parse fade seconds: {}", seconds));
+                },
+                _ => return Err(ParseError::report("scene expects a name and an optional fade <secs>, or snapshot and a name")),
+            }
+            Box::new(WindowAction {
+                window: "".to_string(),
+                action: "scene".to_string(),
+                settings: args[2..].iter().map(|s| s.to_string()).collect(),
+                chan,
+            }) as Box<dyn EventAction>
+        },
+        "act" if args.get(1) == Some(&"osc") && args.get(2) == Some(&"send") => {
+            if args.len() < 5 {
+                return Err(ParseError::report("osc send expects a host:port and an address"));
+            }
+            if !args[4].starts_with('/') {
+                return Err(ParseError::report_string(format!("OSC addresses start with /, got {}", args[4])));
+            }
+            let sender = match OscSender::new(args[3]) {
+                Ok(s) => s,
+                Err(err) => return Err(ParseError::report_string(err)),
+            };
+            let values = args[5..].iter().map(|a| osc::parse_value(a)).collect::<Vec<osc::OscValue>>();
+            Box::new(OscSendAction {
+                sender,
+                packet: osc::encode_message(args[4], &values),
+            }) as Box<dyn EventAction>
+        },
         "act" => {
+            // control socket lines arrive unchecked, so don't index past the end
+            let expected = match args.get(2) {
+                Some(&"prop") => 7,
+                Some(&"play") | Some(&"window") => 4,
+                Some(&"seek") => 5,
+                _ => 3,
+            };
+            if args.len() < expected {
+                return Err(ParseError::report_string(format!("Too few arguments: {}", args.join(" "))));
+            }
             let elem = match pattern.pipes.get(args[1]) {
                 Some(e) => e.0.clone(),
                 None => return Err(ParseError::report_string(format!("Unknown pipeline: {}", args[1])))
@@ -210,6 +466,7 @@ fn parse_single_event(pattern: &Pattern, chan: crossbeam_channel::Sender<String>
                         Some(e) => e,
                         None => return Err(ParseError::report_string(format!("Unknown element: {}", args[3]))),
                     };
+                    catch_bail_annotate!(check_property(&mod_elem, args[4], args[5], &args[6..].join(" ")), "prop");
                     Box::new(SetPropAction {
                         element: mod_elem,
                         prop: args[4].to_string(),
@@ -218,6 +475,9 @@ fn parse_single_event(pattern: &Pattern, chan: crossbeam_channel::Sender<String>
                     }) as Box<dyn EventAction>
                 },
                 "play" => {
+                    if !["start", "pause", "ready", "null", "restart"].contains(&args[3]) {
+                        return Err(ParseError::report_string(format!("Unknown pipeline state: {}", args[3])));
+                    }
                     Box::new(PlayAction {
                         pipeline: elem,
                         action: args[3].to_string(),
@@ -238,7 +498,36 @@ fn parse_single_event(pattern: &Pattern, chan: crossbeam_channel::Sender<String>
                         time,
                     }) as Box<dyn EventAction>
                 },
+                "fallback" => {
+                    if args.len() != 5 {
+                        return Err(ParseError::report("fallback expects an element and a location"));
+                    }
+                    let src = match pipeline.by_name(args[3]) {
+                        Some(e) => e,
+                        None => return Err(ParseError::report_string(format!("Unknown element: {}", args[3]))),
+                    };
+                    if src.find_property("location").is_none() {
+                        return Err(ParseError::report_string(format!("Element {} has no location", args[3])));
+                    }
+                    Box::new(FallbackAction {
+                        pipeline,
+                        element: src,
+                        location: args[4].to_string(),
+                    }) as Box<dyn EventAction>
+                },
                 "window" => {
+                    // shapes are checked, and masks loaded, before the show needs them
+                    let shape = match args[3] {
+                        "shape" => Some(&args[4..]),
+                        "shape-scale" if args.len() > 10 => Some(&args[10..]),
+                        "shape-scale" => return Err(ParseError::report("shape-scale expects a pipeline, times, scales, a path and a shape")),
+                        _ => None,
+                    };
+                    if let Some(shape) = shape {
+                        if let Err(err) = Shape::parse(shape, &pattern.masks) {
+                            return Err(ParseError::report_string(err));
+                        }
+                    }
                     Box::new(WindowAction {
                         window: args[1].to_string(),
                         action: args[3].to_string(),
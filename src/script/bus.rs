@@ -0,0 +1,177 @@
+/*
+ * Copyright 2022 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use gstreamer::prelude::{Cast, ElementExt, GstObjectExt};
+use gtk::glib::{ObjectExt, SignalHandlerId};
+use serde_json::json;
+
+use crate::http;
+use crate::script::action::{fire, EventAction};
+
+// Runs the events whenever the pipeline's bus emits the detailed message
+// signal, e.g. "message::eos", and the filter accepts the message.
+pub fn connect_callback<F>(pipeline: &gstreamer::Pipeline, signal: &str, filter: F, events: Vec<Box<dyn EventAction>>) -> SignalHandlerId
+    where F: Fn(&gstreamer::Message) -> bool + Send + Sync + 'static,
+{
+    let bus = pipeline.bus().unwrap();
+    bus.add_signal_watch();
+    let label = format!("callback {} {}", pipeline.name(), signal.trim_start_matches("message::"));
+    bus.connect(signal, true,
+                move |values| {
+                    let msg = match values[1].get::<gstreamer::Message>() {
+                        Ok(m) => m,
+                        Err(_) => return None,
+                    };
+                    if filter(&msg) {
+                        fire(label.as_str(), &events);
+                    }
+                    None
+                }
+    )
+}
+
+// Accepts state changes of the pipeline itself, rather than its elements,
+// into the given state.
+pub fn state_filter(pipeline: &gstreamer::Pipeline, state: gstreamer::State) -> impl Fn(&gstreamer::Message) -> bool {
+    let pipeline = pipeline.clone().upcast::<gstreamer::Object>();
+    move |msg| {
+        if msg.src().as_ref() != Some(&pipeline) {
+            return false;
+        }
+        match msg.view() {
+            gstreamer::MessageView::StateChanged(change) => change.current() == state && change.old() != state,
+            _ => false,
+        }
+    }
+}
+
+// Accepts the buffering message that first reaches the percentage, and again
+// after buffering drops back below it.
+pub fn buffering_filter(percent: i32) -> impl Fn(&gstreamer::Message) -> bool {
+    let reached = AtomicBool::new(false);
+    move |msg| {
+        match msg.view() {
+            gstreamer::MessageView::Buffering(buffering) => {
+                if buffering.percent() >= percent {
+                    !reached.swap(true, Ordering::SeqCst)
+                } else {
+                    reached.store(false, Ordering::SeqCst);
+                    false
+                }
+            },
+            _ => false,
+        }
+    }
+}
+
+// How far the level has to fall back past the threshold before the trigger
+// can fire again, so it doesn't chatter around the threshold.
+const LEVEL_HYSTERESIS_DB: f64 = 3.0;
+
+struct LevelState {
+    armed: bool,
+    since: Option<Instant>,
+}
+
+// Accepts level messages from the named element once the RMS level, of one
+// channel or the loudest, has stayed above or below the threshold for the
+// hold time.
+pub fn level_filter(element: String, above: bool, db: f64, channel: Option<usize>, hold: Duration) -> impl Fn(&gstreamer::Message) -> bool {
+    let state = Mutex::new(LevelState { armed: true, since: None });
+    move |msg| {
+        if msg.src().map(|s| s.name() != element.as_str()).unwrap_or(true) {
+            return false;
+        }
+        let s = match msg.structure() {
+            Some(s) if s.name() == "level" => s,
+            _ => return false,
+        };
+        let rms = match s.get::<gtk::glib::ValueArray>("rms") {
+            Ok(r) => r.iter().filter_map(|v| v.get::<f64>().ok()).collect::<Vec<f64>>(),
+            Err(_) => return false,
+        };
+        let level = match channel {
+            Some(c) => match rms.get(c) {
+                Some(l) => *l,
+                None => return false,
+            },
+            None => rms.into_iter().fold(f64::NEG_INFINITY, f64::max),
+        };
+        let (crossed, reset) = if above {
+            (level > db, level < db - LEVEL_HYSTERESIS_DB)
+        } else {
+            (level < db, level > db + LEVEL_HYSTERESIS_DB)
+        };
+        let mut state = state.lock().unwrap();
+        if !state.armed {
+            if reset {
+                state.armed = true;
+            }
+            return false;
+        }
+        if !crossed {
+            state.since = None;
+            return false;
+        }
+        let since = *state.since.get_or_insert_with(Instant::now);
+        if since.elapsed() >= hold {
+            state.armed = false;
+            state.since = None;
+            return true;
+        }
+        false
+    }
+}
+
+// Logs every error and warning the pipeline posts, so failures are visible
+// even without an error callback.
+pub fn log_problems(pipeline: &gstreamer::Pipeline) {
+    let bus = pipeline.bus().unwrap();
+    bus.add_signal_watch();
+    let name = pipeline.name().to_string();
+    bus.connect("message", true,
+                move |values| {
+                    let msg = match values[1].get::<gstreamer::Message>() {
+                        Ok(m) => m,
+                        Err(_) => return None,
+                    };
+                    let src = msg.src().map(|s| s.path_string().to_string()).unwrap_or_default();
+                    match msg.view() {
+                        gstreamer::MessageView::Error(err) => {
+                            println!("Error from pipeline {} ({}): {} ({})", name, src, err.error(), err.debug().unwrap_or_default());
+                            http::publish(json!({"type": "error", "pipeline": name, "source": src, "message": err.error().to_string()}));
+                        },
+                        gstreamer::MessageView::Warning(warning) => {
+                            println!("Warning from pipeline {} ({}): {} ({})", name, src, warning.error(), warning.debug().unwrap_or_default());
+                            http::publish(json!({"type": "warning", "pipeline": name, "source": src, "message": warning.error().to_string()}));
+                        },
+                        gstreamer::MessageView::Eos(_) => {
+                            http::publish(json!({"type": "eos", "pipeline": name}));
+                        },
+                        // only the pipeline's own state, not every element's
+                        gstreamer::MessageView::StateChanged(change) if src == format!("/{}", name) => {
+                            http::publish(json!({"type": "state", "pipeline": name, "state": format!("{:?}", change.current()).to_lowercase()}));
+                        },
+                        _ => (),
+                    }
+                    None
+                }
+    );
+}
@@ -14,19 +14,23 @@
  * limitations under the License.
 */
 
-use std::borrow::BorrowMut;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fs;
+use std::time::Duration;
 
 use gstreamer::prelude::{Cast, ElementExt, GstBinExt};
 use gtk::glib::ObjectExt;
 
 use crate::{catch_bail, catch_bail_annotate, none_bail};
 use crate::error::{ParseError, ParseResult};
+use crate::gtk_manage::monitor::Coord;
 use crate::script::action::set_property;
 
-mod action;
+pub mod action;
+mod bus;
 
+#[derive(Clone, PartialEq)]
 pub enum ParsedSetting {
     Int(i32),
     Float(f64),
@@ -86,6 +90,25 @@ impl Template {
         let rendered = catch_bail!(gstreamer::parse_launch(self.pipeline.as_str()), "Could not render pipeline");
         catch_bail!(set_property(rendered.clone(), "name".to_string(), "string".to_string(), name), "Could not name pipeline");
         let pipeline = catch_bail!(rendered.clone().dynamic_cast::<gstreamer::Pipeline>(), "Could not cast pipeline to pipeline");
+        let external = self.configure(&pipeline, args)?;
+        Ok((pipeline, external))
+    }
+
+    // Sets the template's properties on a pipeline it generated, returning
+    // the settings meant for the mixer rather than an element.
+    pub fn configure(&self, pipeline: &gstreamer::Pipeline, args: &[&str]) -> ParseResult<HashMap<String, ParsedSetting>> {
+        self.settings_for(pipeline, args, true)
+    }
+
+    // Like configure, but only checks the properties could be set.
+    pub fn check(&self, pipeline: &gstreamer::Pipeline, args: &[&str]) -> ParseResult<HashMap<String, ParsedSetting>> {
+        self.settings_for(pipeline, args, false)
+    }
+
+    fn settings_for(&self, pipeline: &gstreamer::Pipeline, args: &[&str], write: bool) -> ParseResult<HashMap<String, ParsedSetting>> {
+        if args.len() != self.arg_count {
+            return Err(ParseError::report("Incorrect number of arguments"));
+        };
         let mut external = HashMap::new();
 
         for (key, value) in self.settings.iter() {
@@ -110,17 +133,28 @@ impl Template {
                         "string" => {
                             ParsedSetting::String(actual_val.to_string())
                         },
+                        // pixels when given an integer, otherwise a fraction of the monitor
+                        "coord" => {
+                            match actual_val.parse::<i32>() {
+                                Ok(i) => ParsedSetting::Int(i),
+                                Err(_) => ParsedSetting::Float(catch_bail!(actual_val.parse(), "Failed to parse setting as coord")),
+                            }
+                        },
                         _ => return Err(ParseError::report_string(format!("Unknown type: {}", type_))),
                     };
                     external.insert(prop.clone(), ext);
                 } else {
                     let elem_prop = none_bail!(pipeline.by_name(key), format!("Could not obtain element {}", key));
-                    catch_bail_annotate!(action::set_property(elem_prop, prop.clone(), type_.clone(), actual_val.to_string()), "set_property");
+                    if write {
+                        catch_bail_annotate!(action::set_property(elem_prop, prop.clone(), type_.clone(), actual_val.to_string()), "set_property");
+                    } else {
+                        catch_bail_annotate!(action::check_property(&elem_prop, prop, type_, actual_val), "check_property");
+                    }
                 }
             }
         };
 
-        Ok((pipeline, external))
+        Ok(external)
     }
 }
 
@@ -138,30 +172,300 @@ static MP4INPUT_PATTERN: &str =
     war";
 
 static ALSAOUTPUT_PATTERN: &str =
-    "raw aoutput 0 proxysrc name=audio_in ! alsasink name=sink\n\
+    "raw aoutput 0 proxysrc name=audio_in ! level name=level interval=50000000 ! alsasink name=sink\n\
     war";
 
 static XOUTPUT_PATTERN: &str =
     "raw xoutput 4 proxysrc name=video_in ! xvimagesink name=sink\n\
     raw gtktag string window\n\
-    raw x int $1\n\
-    raw y int $2\n\
-    raw width int $3\n\
-    raw height int $4\n\
+    raw x coord $1\n\
+    raw y coord $2\n\
+    raw width coord $3\n\
+    raw height coord $4\n\
+    war";
+
+static MONOUTPUT_PATTERN: &str =
+    "raw monoutput 5 proxysrc name=video_in ! xvimagesink name=sink\n\
+    raw gtktag string window\n\
+    raw monitor string $1\n\
+    raw x coord $2\n\
+    raw y coord $3\n\
+    raw width coord $4\n\
+    raw height coord $5\n\
     war";
 
+// A grid of cells windows can be placed in, named r<row>c<column> from r1c1,
+// or <name>.r<row>c<column> for a named grid.
+pub struct GridLayout {
+    pub name: Option<String>,
+    pub rows: u32,
+    pub cols: u32,
+    pub gap: ParsedSetting,
+    pub monitor: Option<String>,
+}
+
+impl GridLayout {
+    fn parse_command(args: &[&str]) -> ParseResult<GridLayout> {
+        let (args, name) = match args {
+            [rest @ .., "as", name] => (rest, Some(name.to_string())),
+            _ => (args, None),
+        };
+        if args.len() < 2 || args.len() > 4 {
+            return Err(ParseError::report("grid expects rows, columns and an optional gap and monitor"));
+        }
+        let rows = catch_bail!(args[0].parse::<u32>(), format!("Could not parse rows: {}", args[0]));
+        let cols = catch_bail!(args[1].parse::<u32>(), format!("Could not parse columns: {}", args[1]));
+        if rows == 0 || cols == 0 {
+            return Err(ParseError::report("grid needs at least one row and column"));
+        }
+        let mut gap = ParsedSetting::Int(0);
+        let mut monitor = None;
+        let mut rest = args[2..].iter();
+        if let Some(s) = rest.next() {
+            if let Ok(i) = s.parse::<i32>() {
+                gap = ParsedSetting::Int(i);
+            } else if let Ok(f) = s.parse::<f64>() {
+                gap = ParsedSetting::Float(f);
+            } else {
+                monitor = Some(s.to_string());
+            }
+        }
+        if let Some(s) = rest.next() {
+            if monitor.is_some() {
+                return Err(ParseError::report("grid gap must come before the monitor"));
+            }
+            monitor = Some(s.to_string());
+        }
+        Ok(GridLayout { name, rows, cols, gap, monitor })
+    }
+}
+
+pub struct KeyTrigger {
+    pub key: gtk::gdk::keys::Key,
+    pub modifiers: gtk::gdk::ModifierType,
+    pub events: Vec<Box<dyn action::EventAction>>,
+}
+
+pub enum PointerKind {
+    // any button when not given
+    Click(Option<u32>),
+    Enter,
+    Leave,
+}
+
+pub struct PointerTrigger {
+    pub window: String,
+    pub kind: PointerKind,
+    pub events: Vec<Box<dyn action::EventAction>>,
+}
+
+pub struct SignalTrigger {
+    pub signal: i32,
+    pub events: Vec<Box<dyn action::EventAction>>,
+}
+
+pub struct OscTrigger {
+    pub address: String,
+    pub events: Vec<Box<dyn action::EventAction>>,
+    // actions that take the message's arguments
+    pub template: Option<action::ActionTemplate>,
+}
+
+pub enum MidiKind {
+    Note { channel: u8, note: u8, min_velocity: u8 },
+    Control { channel: u8, param: u32 },
+}
+
+pub struct MidiTrigger {
+    pub kind: MidiKind,
+    pub events: Vec<Box<dyn action::EventAction>>,
+    // actions that take the velocity or controller value
+    pub template: Option<action::ActionTemplate>,
+}
+
+pub struct MarkTrigger {
+    pub name: String,
+    pub events: Vec<Box<dyn action::EventAction>>,
+}
+
+pub struct AfterTrigger {
+    pub seconds: f64,
+    // counts from the named mark, or from startup when None
+    pub since: Option<String>,
+    pub events: Vec<Box<dyn action::EventAction>>,
+}
+
+pub struct ClockTrigger {
+    // local time of day
+    pub seconds: u32,
+    pub events: Vec<Box<dyn action::EventAction>>,
+}
+
+#[derive(PartialEq)]
+pub enum Follow {
+    // waits for the next GO
+    Manual,
+    // runs the next cue straight away
+    Continue,
+    // runs the next cue the given seconds after this one's GO. Cue actions
+    // take no time, so unlike QLab's autofollow this doesn't wait for the cue
+    // to finish; it is closer to an autocontinue with a pre-wait.
+    After(f64),
+}
+
+pub struct Cue {
+    pub name: String,
+    pub follow: Follow,
+    pub events: Vec<Box<dyn action::EventAction>>,
+}
+
+// One part of a scene's target state.
+pub enum SceneSetting {
+    Prop { element: gstreamer::Element, prop: String, type_: String, value: String },
+    State { pipeline: gstreamer::Pipeline, state: gstreamer::State },
+    Visible { window: String, visible: bool },
+    // coordinates as in window actions, placed against the window's placement
+    Position { window: String, x: Coord, y: Coord },
+}
+
+pub struct Scene {
+    // replaced in place by a snapshot
+    pub settings: RefCell<Vec<SceneSetting>>,
+}
+
+impl Scene {
+    fn parse_setting(pattern: &Pattern, args: &[&str]) -> ParseResult<SceneSetting> {
+        match args[..] {
+            [pipe, "prop", elem, prop, type_, ref value @ ..] if !value.is_empty() => {
+                let (pipeline, _) = none_bail!(pattern.pipes.get(pipe), format!("Unknown pipeline: {}", pipe));
+                let pipeline = pipeline.clone().dynamic_cast::<gstreamer::Pipeline>().unwrap();
+                let element = none_bail!(pipeline.by_name(elem), format!("Unknown element: {}", elem));
+                let value = value.join(" ");
+                catch_bail_annotate!(action::check_property(&element, prop, type_, &value), "prop");
+                Ok(SceneSetting::Prop {
+                    element,
+                    prop: prop.to_string(),
+                    type_: type_.to_string(),
+                    value,
+                })
+            },
+            [pipe, "play", state] => {
+                let (pipeline, _) = none_bail!(pattern.pipes.get(pipe), format!("Unknown pipeline: {}", pipe));
+                let state = match state {
+                    "start" => gstreamer::State::Playing,
+                    "pause" => gstreamer::State::Paused,
+                    "ready" => gstreamer::State::Ready,
+                    "null" => gstreamer::State::Null,
+                    s => return Err(ParseError::report_string(format!("Unknown pipeline state: {}", s))),
+                };
+                Ok(SceneSetting::State {
+                    pipeline: pipeline.clone().dynamic_cast::<gstreamer::Pipeline>().unwrap(),
+                    state,
+                })
+            },
+            [window, "window", ..] if !pattern.has_window(window) => {
+                Err(ParseError::report_string(format!("Unknown window: {}", window)))
+            },
+            [window, "window", "show"] => Ok(SceneSetting::Visible { window: window.to_string(), visible: true }),
+            [window, "window", "hide"] => Ok(SceneSetting::Visible { window: window.to_string(), visible: false }),
+            [window, "window", "at", x, y] => Ok(SceneSetting::Position {
+                window: window.to_string(),
+                x: catch_bail_annotate!(Coord::parse(x), "window at"),
+                y: catch_bail_annotate!(Coord::parse(y), "window at"),
+            }),
+            _ => Err(ParseError::report_string(format!("Unknown scene setting: {}", args.join(" ")))),
+        }
+    }
+}
+
+#[derive(PartialEq)]
+pub enum Repeat {
+    Once,
+    // re-arms whenever the pipeline goes back before the trigger time
+    EveryPass,
+}
+
+#[derive(PartialEq)]
+pub enum Jump {
+    Fire,
+    Skip,
+}
+
+pub struct ProgressTrigger {
+    pub pipe: String,
+    pub nanos: u64,
+    pub repeat: Repeat,
+    // what to do when a forward seek jumps over the trigger time
+    pub jump: Jump,
+    pub events: Vec<Box<dyn action::EventAction>>,
+}
+
+pub struct EveryTrigger {
+    pub interval: f64,
+    // counts pipeline position when set, otherwise seconds since startup
+    pub pipe: Option<String>,
+    pub from: f64,
+    pub until: Option<f64>,
+    pub events: Vec<Box<dyn action::EventAction>>,
+}
+
+fn parse_time_of_day(s: &str) -> ParseResult<u32> {
+    let parts = s.split(':').collect::<Vec<&str>>();
+    if parts.len() < 2 || parts.len() > 3 {
+        return Err(ParseError::report_string(format!("Expected HH:MM or HH:MM:SS, got {}", s)));
+    }
+    let mut seconds = 0;
+    for (part, (unit, limit)) in parts.iter().zip([(3600, 24), (60, 60), (1, 60)]) {
+        let value = catch_bail!(part.parse::<u32>(), format!("Could not parse time of day: {}", s));
+        if value >= limit {
+            return Err(ParseError::report_string(format!("Time of day out of range: {}", s)));
+        }
+        seconds += value * unit;
+    }
+    Ok(seconds)
+}
+
+// Timers wait up to a year; longer or non-finite waits can't be timed.
+fn check_delay(seconds: f64) -> ParseResult<f64> {
+    if !seconds.is_finite() || !(0.0..=365.0 * 24.0 * 3600.0).contains(&seconds) {
+        return Err(ParseError::report_string(format!("Delay out of range: {}", seconds)));
+    }
+    Ok(seconds)
+}
+
 pub struct Pattern {
     pub blocks: HashMap<String, Template>,
     pub pipes: HashMap<String, (gstreamer::Pipeline, HashMap<String, ParsedSetting>)>,
+    pub layouts: Vec<GridLayout>,
     pub listen: Option<crossbeam_channel::Receiver<String>>,
-    pub time_events: HashMap<(String, u64), Vec<Box<dyn action::EventAction>>>,
+    // for actions parsed after loading
+    pub send: Option<crossbeam_channel::Sender<String>>,
+    pub time_events: Vec<ProgressTrigger>,
     pub pre_events: Vec<Box<dyn action::EventAction>>,
+    pub key_events: Vec<KeyTrigger>,
+    pub pointer_events: Vec<PointerTrigger>,
+    pub signal_events: Vec<SignalTrigger>,
+    pub osc_events: Vec<OscTrigger>,
+    pub midi_events: Vec<MidiTrigger>,
+    pub mark_events: Vec<MarkTrigger>,
+    // the cue stack, in the order GO runs it
+    pub cues: Vec<Cue>,
+    pub scenes: HashMap<String, Scene>,
+    // mask images for window shapes, by path
+    pub masks: RefCell<HashMap<String, cairo::ImageSurface>>,
+    pub after_events: Vec<AfterTrigger>,
+    pub clock_events: Vec<ClockTrigger>,
+    pub every_events: Vec<EveryTrigger>,
+    // beats per minute and the time of the first beat, in seconds
+    pub tempo: Option<(f64, f64)>,
+    // callback and level handlers, so a reload can remove them
+    pub bus_handlers: Vec<(gstreamer::Bus, gtk::glib::SignalHandlerId)>,
 }
 
 impl Pattern {
     fn default() -> Pattern {
         let mut pipelines = HashMap::new();
-        let premade = vec![MP3INPUT_PATTERN, MP4INPUT_PATTERN, XOUTPUT_PATTERN, ALSAOUTPUT_PATTERN];
+        let premade = vec![MP3INPUT_PATTERN, MP4INPUT_PATTERN, XOUTPUT_PATTERN, MONOUTPUT_PATTERN, ALSAOUTPUT_PATTERN];
         for pre in premade {
             let mut lines = pre.split('\n');
             let args = lines.next().unwrap().split_whitespace().collect::<Vec<&str>>();
@@ -171,22 +475,103 @@ impl Pattern {
         Pattern {
             blocks: pipelines,
             pipes: HashMap::new(),
+            layouts: Vec::new(),
             listen: None,
-            time_events: HashMap::new(),
+            send: None,
+            time_events: Vec::new(),
             pre_events: Vec::new(),
+            key_events: Vec::new(),
+            pointer_events: Vec::new(),
+            signal_events: Vec::new(),
+            osc_events: Vec::new(),
+            midi_events: Vec::new(),
+            mark_events: Vec::new(),
+            cues: Vec::new(),
+            scenes: HashMap::new(),
+            masks: RefCell::new(HashMap::new()),
+            after_events: Vec::new(),
+            clock_events: Vec::new(),
+            every_events: Vec::new(),
+            tempo: None,
+            bus_handlers: Vec::new(),
+        }
+    }
+
+    // The proxysink and proxysrc a plug line joins.
+    pub fn plug_ends(&self, args: &[&str]) -> ParseResult<(gstreamer::Element, gstreamer::Element)> {
+        if args.len() < 5 {
+            return Err(ParseError::report_string(format!("Too few arguments: {}", args.join(" "))));
+        }
+        let (pipe_a, _) = none_bail!(self.pipes.get(args[2]), format!("No pipe found: {}", args[2]));
+        let (pipe_b, _) = none_bail!(self.pipes.get(args[4]), format!("No pipe found: {}", args[4]));
+        let elem_a = none_bail!(pipe_a.clone().by_name(args[1]), format!("No element found: {}", args[1]));
+        let elem_b = none_bail!(pipe_b.clone().by_name(args[3]), format!("No element found: {}", args[3]));
+        if elem_b.find_property("proxysink").is_none() {
+            return Err(ParseError::report_string(format!("Element {} is not a proxysrc", args[3])));
+        }
+        Ok((elem_a, elem_b))
+    }
+
+    // Whether the named pipeline is tagged to get a window.
+    pub fn has_window(&self, name: &str) -> bool {
+        matches!(self.pipes.get(name), Some((_, tags)) if tags.get("gtktag") == Some(&ParsedSetting::String("window".to_string())))
+    }
+
+    // An empty pattern sharing this one's window action channel.
+    pub fn successor(&self) -> Pattern {
+        let mut pattern = Pattern::default();
+        pattern.listen = self.listen.clone();
+        pattern.send = self.send.clone();
+        pattern
+    }
+
+    pub fn disconnect_handlers(&mut self) {
+        for (bus, handler) in self.bus_handlers.drain(..) {
+            bus.disconnect(handler);
+            bus.remove_signal_watch();
         }
     }
 
+    // Parses a length in seconds, or in beats with a b suffix, e.g. 0.5b.
+    fn parse_interval(&self, s: &str) -> ParseResult<f64> {
+        match s.strip_suffix('b') {
+            Some(beats) => {
+                let (bpm, _) = none_bail!(self.tempo, format!("{} is in beats but no tempo is declared", s));
+                let beats = catch_bail!(beats.parse::<f64>(), format!("Could not parse beats: {}", s));
+                Ok(beats * 60.0 / bpm)
+            },
+            None => Ok(catch_bail!(s.parse::<f64>(), format!("Could not parse seconds: {}", s))),
+        }
+    }
+
+    // Like parse_interval, but beats count from the first beat.
+    fn parse_time(&self, s: &str) -> ParseResult<f64> {
+        let offset = match (s.ends_with('b'), self.tempo) {
+            (true, Some((_, offset))) => offset,
+            _ => 0.0,
+        };
+        Ok(self.parse_interval(s)? + offset)
+    }
+
     pub fn parse_pattern(path: String) -> ParseResult<Pattern> {
         let data = match fs::read_to_string(path) {
             Ok(d) => d,
             Err(err) => return Err(ParseError::report_string(format!("Could not read file: {:?}", err))),
         };
-        let lines = data.split("\n");
-        let mut commands = Vec::new();
         let mut pattern = Pattern::default();
         let (tx, rx) = crossbeam_channel::unbounded();
         pattern.listen = Some(rx);
+        pattern.send = Some(tx);
+        pattern.apply(data.as_str())?;
+        Ok(pattern)
+    }
+
+    // Applies pattern lines, from a file or typed at the console, to this
+    // pattern.
+    pub fn apply(&mut self, data: &str) -> ParseResult<()> {
+        let tx = none_bail!(self.send.clone(), "Pattern has no channel");
+        let lines = data.split("\n");
+        let mut commands = Vec::new();
         // clear comments
         for line in lines {
             if !line.starts_with("//") && line.len() != 0 {
@@ -202,79 +587,441 @@ impl Pattern {
             }
             let line = line.unwrap();
             let args = line.split_whitespace().collect::<Vec<&str>>();
+            // console lines arrive unchecked, so don't index past the end
+            let min_args = match (args.first(), args.get(1)) {
+                (None, _) => continue,
+                (Some(&"raw"), _) | (Some(&"new"), _) => 3,
+                (Some(&"plug"), _) => 5,
+                (Some(&"on"), Some(&"callback")) | (Some(&"on"), Some(&"progress")) => 5,
+                (Some(&"on"), _) => 3,
+                (Some(&"cue"), _) | (Some(&"scene"), _) => 2,
+                _ => 1,
+            };
+            if args.len() < min_args {
+                return Err(ParseError::report_string(format!("Too few arguments: {}", line)));
+            }
             match args[0] {
                 "raw" => {
                     let (name, pipeline) = match Template::parse_command(&args[1..], &mut cmd_iter) {
                         Ok(rst) => rst,
                         Err(err) => return Err(err.annotate("parse_command")),
                     };
-                    pattern.blocks.insert(name, pipeline);
+                    self.blocks.insert(name, pipeline);
                 },
                 "new" => {
                     let key = args[1];
                     let name = args[2].to_string();
                     let pipeline_args = &args[3..];
-                    let block = pattern.blocks.get(key).unwrap();
+                    let block = none_bail!(self.blocks.get(key), format!("Unknown template: {}", key));
+                    if self.pipes.contains_key(&name) {
+                        return Err(ParseError::report_string(format!("Pipeline already exists: {}", name)));
+                    }
+                    // act reads these as actions rather than pipelines
+                    if ["mark", "cue", "scene", "osc"].contains(&name.as_str()) {
+                        return Err(ParseError::report_string(format!("Reserved pipeline name: {}", name)));
+                    }
                     match block.generate(name.clone(), pipeline_args) {
                         Ok((elem, settings)) => {
-                            pattern.pipes.insert(name, (elem, settings));
+                            bus::log_problems(&elem);
+                            self.pipes.insert(name, (elem, settings));
                         },
                         Err(err) => return Err(err.annotate("block.generate")),
                     }
                 },
                 "plug" => {
-                    let (pipe_a, _) = none_bail!(pattern.pipes.get(args[2]), format!("No pipe found: {}", args[2]));
-                    let (pipe_b, _) = none_bail!(pattern.pipes.get(args[4]), format!("No pipe found: {}", args[4]));
-                    let elem_a = none_bail!(pipe_a.clone().by_name(args[1]), format!("No element found: {}", args[1]));
-                    let elem_b = none_bail!(pipe_b.clone().by_name(args[3]), format!("No element found: {}", args[3]));
+                    let (elem_a, elem_b) = self.plug_ends(&args)?;
                     elem_b.set_property("proxysink", elem_a);
                     println!("{}->{} ==> {}->{}", args[2], args[1], args[4], args[3]);
                 },
+                "tempo" => {
+                    let bpm = catch_bail!(none_bail!(args.get(1), "tempo expects beats per minute").parse::<f64>(), "Failed to parse tempo");
+                    if bpm <= 0.0 {
+                        return Err(ParseError::report("tempo must be positive"));
+                    }
+                    let offset = match args.get(2) {
+                        Some(o) => catch_bail!(o.parse::<f64>(), "Failed to parse first beat"),
+                        None => 0.0,
+                    };
+                    self.tempo = Some((bpm, offset));
+                },
+                "layout" => {
+                    match args.get(1) {
+                        Some(&"grid") => {
+                            let layout = catch_bail_annotate!(GridLayout::parse_command(&args[2..]), "layout grid");
+                            // cells of grids with the same name would overwrite each other
+                            if self.layouts.iter().any(|l| l.name == layout.name) {
+                                return match layout.name {
+                                    Some(n) => Err(ParseError::report_string(format!("Grid already exists: {}", n))),
+                                    None => Err(ParseError::report("Only one grid can be unnamed, name others with as <name>")),
+                                };
+                            }
+                            self.layouts.push(layout);
+                        },
+                        Some(l) => return Err(ParseError::report_string(format!("Unknown layout: {}", l))),
+                        None => return Err(ParseError::report("No layout given")),
+                    }
+                },
+                "cue" => {
+                    let name = args[1].to_string();
+                    if self.cues.iter().any(|c| c.name == name) {
+                        return Err(ParseError::report_string(format!("Cue already exists: {}", name)));
+                    }
+                    let follow = match args[2..] {
+                        [] => Follow::Manual,
+                        ["autocontinue"] => Follow::Continue,
+                        ["autofollow", delay] => {
+                            let seconds = catch_bail_annotate!(self.parse_interval(delay), "autofollow");
+                            Follow::After(catch_bail_annotate!(check_delay(seconds), "autofollow"))
+                        },
+                        _ => return Err(ParseError::report("cue expects a name and autofollow <delay> or autocontinue")),
+                    };
+                    let mut events = Vec::new();
+                    loop {
+                        let line = none_bail!(cmd_iter.next(), format!("cue {} ended without end", name));
+                        let words = line.split_whitespace().collect::<Vec<&str>>();
+                        match words.first() {
+                            Some(&"end") => break,
+                            None => continue,
+                            _ => (),
+                        }
+                        events.extend(catch_bail_annotate!(action::parse_leading_event(self, tx.clone(), &words, &mut cmd_iter), format!("cue {}", name)));
+                    }
+                    self.cues.push(Cue { name, follow, events });
+                },
+                "scene" => {
+                    let name = args[1].to_string();
+                    if args.len() > 2 {
+                        return Err(ParseError::report("scene expects only a name"));
+                    }
+                    if self.scenes.contains_key(&name) {
+                        return Err(ParseError::report_string(format!("Scene already exists: {}", name)));
+                    }
+                    let mut settings = Vec::new();
+                    loop {
+                        let line = none_bail!(cmd_iter.next(), format!("scene {} ended without end", name));
+                        let words = line.split_whitespace().collect::<Vec<&str>>();
+                        match words.first() {
+                            Some(&"end") => break,
+                            None => continue,
+                            _ => (),
+                        }
+                        settings.push(catch_bail_annotate!(Scene::parse_setting(self, &words), format!("scene {}", name)));
+                    }
+                    self.scenes.insert(name, Scene { settings: RefCell::new(settings) });
+                },
                 "on" => {
                     let type_ = args[1];
                     match type_ {
                         "callback" => {
-                            let (pipe, _) = none_bail!(pattern.pipes.get(args[2]), format!("No element found: {}", args[2]));
+                            let (pipe, _) = none_bail!(self.pipes.get(args[2]), format!("No element found: {}", args[2]));
+                            let pipe = pipe.clone();
                             let event = args[3];
-                            match event {
-                                "end" => {
-                                    let bus = pipe.bus().unwrap();
-                                    let events = catch_bail_annotate!(action::parse_leading_event(&mut pattern, tx.clone(), &args[4..], &mut cmd_iter), "callback end");
-                                    bus.add_signal_watch();
-                                    bus.connect("message::eos", true,
-                                                move |_| {
-                                                    for event in &events {
-                                                        event.exec();
-                                                    }
-                                                    None
-                                                }
-                                    );
-                                }
+                            let (signal, filter, actions_at): (&str, Box<dyn Fn(&gstreamer::Message) -> bool + Send + Sync>, usize) = match event {
+                                "end" => ("message::eos", Box::new(|_| true), 4),
+                                "error" => ("message::error", Box::new(|_| true), 4),
+                                "warning" => ("message::warning", Box::new(|_| true), 4),
+                                "async-done" => ("message::async-done", Box::new(|_| true), 4),
+                                "segment-done" => ("message::segment-done", Box::new(|_| true), 4),
+                                "state" => {
+                                    let state = match args.get(4) {
+                                        Some(&"start") | Some(&"playing") => gstreamer::State::Playing,
+                                        Some(&"pause") | Some(&"paused") => gstreamer::State::Paused,
+                                        Some(&"ready") => gstreamer::State::Ready,
+                                        Some(&"null") => gstreamer::State::Null,
+                                        s => return Err(ParseError::report_string(format!("Unknown pipeline state: {:?}", s))),
+                                    };
+                                    ("message::state-changed", Box::new(bus::state_filter(&pipe, state)), 5)
+                                },
+                                "buffering" => {
+                                    let percent = catch_bail!(none_bail!(args.get(4), "buffering expects a percentage").parse::<i32>(), "Failed to parse buffering percentage");
+                                    ("message::buffering", Box::new(bus::buffering_filter(percent)), 5)
+                                },
                                 e => return Err(ParseError::report_string(format!("Unknown callback: {}", e)))
+                            };
+                            if actions_at >= args.len() {
+                                return Err(ParseError::report("callback expects actions"));
                             }
+                            let events = catch_bail_annotate!(action::parse_leading_event(self, tx.clone(), &args[actions_at..], &mut cmd_iter), format!("callback {}", event));
+                            let handler = bus::connect_callback(&pipe, signal, filter, events);
+                            self.bus_handlers.push((pipe.bus().unwrap(), handler));
+                        },
+                        "level" => {
+                            if args.len() < 7 {
+                                return Err(ParseError::report("level expects a pipeline, element, above or below, a level and actions"));
+                            }
+                            let (pipe, _) = none_bail!(self.pipes.get(args[2]), format!("No element found: {}", args[2]));
+                            let pipe = pipe.clone();
+                            none_bail!(pipe.by_name(args[3]), format!("No element found: {}", args[3]));
+                            let above = match args[4] {
+                                "above" => true,
+                                "below" => false,
+                                a => return Err(ParseError::report_string(format!("Expected above or below, got {}", a))),
+                            };
+                            let db = catch_bail!(args[5].parse::<f64>(), "Failed to parse level");
+                            // optional channel N and hold MS, in either order
+                            let mut actions_at = 6;
+                            let mut channel = None;
+                            let mut hold = 0;
+                            loop {
+                                match (args.get(actions_at), args.get(actions_at + 1)) {
+                                    (Some(&"channel"), Some(c)) => channel = Some(catch_bail!(c.parse::<usize>(), format!("Failed to parse channel: {}", c))),
+                                    (Some(&"hold"), Some(h)) => hold = catch_bail!(h.parse::<u64>(), format!("Failed to parse hold time: {}", h)),
+                                    _ => break,
+                                }
+                                actions_at += 2;
+                            }
+                            if actions_at >= args.len() {
+                                return Err(ParseError::report("level expects actions"));
+                            }
+                            let events = catch_bail_annotate!(action::parse_leading_event(self, tx.clone(), &args[actions_at..], &mut cmd_iter), "level, parse");
+                            let filter = bus::level_filter(args[3].to_string(), above, db, channel, Duration::from_millis(hold));
+                            let handler = bus::connect_callback(&pipe, "message::element", filter, events);
+                            self.bus_handlers.push((pipe.bus().unwrap(), handler));
+                        },
+                        "key" => {
+                            let name = none_bail!(args.get(2), "key expects a key name");
+                            let key = gtk::gdk::keys::Key::from_name(name);
+                            if key == gtk::gdk::keys::constants::VoidSymbol {
+                                return Err(ParseError::report_string(format!("Unknown key: {}", name)));
+                            }
+                            let mut modifiers = gtk::gdk::ModifierType::empty();
+                            let mut actions_at = 3;
+                            while let Some(m) = args.get(actions_at) {
+                                modifiers |= match *m {
+                                    "ctrl" => gtk::gdk::ModifierType::CONTROL_MASK,
+                                    "shift" => gtk::gdk::ModifierType::SHIFT_MASK,
+                                    "alt" => gtk::gdk::ModifierType::MOD1_MASK,
+                                    "super" => gtk::gdk::ModifierType::SUPER_MASK,
+                                    _ => break,
+                                };
+                                actions_at += 1;
+                            }
+                            if actions_at >= args.len() {
+                                return Err(ParseError::report("key expects actions"));
+                            }
+                            let events = catch_bail_annotate!(action::parse_leading_event(self, tx.clone(), &args[actions_at..], &mut cmd_iter), "key, parse");
+                            self.key_events.push(KeyTrigger {
+                                key: key.to_lower(),
+                                modifiers,
+                                events,
+                            });
+                        },
+                        "click" | "hover" => {
+                            let window = none_bail!(args.get(2), format!("{} expects a window", type_));
+                            if !self.has_window(window) {
+                                return Err(ParseError::report_string(format!("Not a window pipeline: {}", window)));
+                            }
+                            let (kind, actions_at) = if type_ == "click" {
+                                match args.get(3).map(|b| b.parse::<u32>()) {
+                                    Some(Ok(button)) => (PointerKind::Click(Some(button)), 4),
+                                    _ => (PointerKind::Click(None), 3),
+                                }
+                            } else {
+                                match args.get(3) {
+                                    Some(&"enter") => (PointerKind::Enter, 4),
+                                    Some(&"leave") => (PointerKind::Leave, 4),
+                                    h => return Err(ParseError::report_string(format!("Expected enter or leave, got {:?}", h))),
+                                }
+                            };
+                            if actions_at >= args.len() {
+                                return Err(ParseError::report_string(format!("{} expects actions", type_)));
+                            }
+                            let events = catch_bail_annotate!(action::parse_leading_event(self, tx.clone(), &args[actions_at..], &mut cmd_iter), format!("{}, parse", type_));
+                            self.pointer_events.push(PointerTrigger {
+                                window: window.to_string(),
+                                kind,
+                                events,
+                            });
+                        },
+                        "signal" => {
+                            let signal = match args.get(2) {
+                                Some(&"usr1") => libc::SIGUSR1,
+                                Some(&"usr2") => libc::SIGUSR2,
+                                Some(&"hup") => libc::SIGHUP,
+                                s => return Err(ParseError::report_string(format!("Expected usr1, usr2 or hup, got {:?}", s))),
+                            };
+                            if args.len() < 4 {
+                                return Err(ParseError::report("signal expects actions"));
+                            }
+                            let events = catch_bail_annotate!(action::parse_leading_event(self, tx.clone(), &args[3..], &mut cmd_iter), "signal, parse");
+                            self.signal_events.push(SignalTrigger {
+                                signal,
+                                events,
+                            });
+                        },
+                        "osc" => {
+                            let address = none_bail!(args.get(2), "osc expects an address");
+                            if !address.starts_with('/') {
+                                return Err(ParseError::report_string(format!("OSC addresses start with /, got {}", address)));
+                            }
+                            if args.len() < 4 {
+                                return Err(ParseError::report("osc expects actions"));
+                            }
+                            let (events, template) = if action::ActionTemplate::uses_values(&args[3..]) {
+                                (Vec::new(), Some(catch_bail_annotate!(action::ActionTemplate::parse(self, &args[3..]), "osc")))
+                            } else {
+                                (catch_bail_annotate!(action::parse_leading_event(self, tx.clone(), &args[3..], &mut cmd_iter), "osc, parse"), None)
+                            };
+                            self.osc_events.push(OscTrigger {
+                                address: address.to_string(),
+                                events,
+                                template,
+                            });
+                        },
+                        "midi" => {
+                            if args.len() < 6 {
+                                return Err(ParseError::report("midi expects note or cc, a channel, a number and actions"));
+                            }
+                            let channel = catch_bail!(args[3].parse::<u8>(), format!("Could not parse MIDI channel: {}", args[3]));
+                            if !(1..=16).contains(&channel) {
+                                return Err(ParseError::report_string(format!("MIDI channels are 1 to 16, got {}", channel)));
+                            }
+                            let mut actions_at = 5;
+                            let kind = match args[2] {
+                                "note" => {
+                                    let note = catch_bail!(args[4].parse::<u8>(), format!("Could not parse note: {}", args[4]));
+                                    let mut min_velocity = 1;
+                                    if let Some(v) = args[5].strip_prefix("velocity>") {
+                                        min_velocity = catch_bail!(v.parse::<u8>(), format!("Could not parse velocity: {}", v)).saturating_add(1);
+                                        actions_at += 1;
+                                    }
+                                    MidiKind::Note { channel, note, min_velocity }
+                                },
+                                "cc" => {
+                                    let param = catch_bail!(args[4].parse::<u32>(), format!("Could not parse controller: {}", args[4]));
+                                    MidiKind::Control { channel, param }
+                                },
+                                k => return Err(ParseError::report_string(format!("Expected note or cc, got {}", k))),
+                            };
+                            if actions_at >= args.len() {
+                                return Err(ParseError::report("midi expects actions"));
+                            }
+                            let (events, template) = if action::ActionTemplate::uses_values(&args[actions_at..]) {
+                                (Vec::new(), Some(catch_bail_annotate!(action::ActionTemplate::parse(self, &args[actions_at..]), "midi")))
+                            } else {
+                                (catch_bail_annotate!(action::parse_leading_event(self, tx.clone(), &args[actions_at..], &mut cmd_iter), "midi, parse"), None)
+                            };
+                            self.midi_events.push(MidiTrigger {
+                                kind,
+                                events,
+                                template,
+                            });
+                        },
+                        "mark" => {
+                            let name = none_bail!(args.get(2), "mark expects a name");
+                            if args.len() < 4 {
+                                return Err(ParseError::report("mark expects actions"));
+                            }
+                            let events = catch_bail_annotate!(action::parse_leading_event(self, tx.clone(), &args[3..], &mut cmd_iter), "mark, parse");
+                            self.mark_events.push(MarkTrigger {
+                                name: name.to_string(),
+                                events,
+                            });
+                        },
+                        "after" => {
+                            let seconds = catch_bail!(none_bail!(args.get(2), "after expects seconds").parse::<f64>(), "Failed to parse seconds");
+                            let seconds = catch_bail_annotate!(check_delay(seconds), "after");
+                            let (since, actions_at) = match (args.get(3), args.get(4)) {
+                                (Some(&"since"), Some(&"start")) => (None, 5),
+                                (Some(&"since"), Some(name)) => (Some(name.to_string()), 5),
+                                (Some(&"since"), None) => return Err(ParseError::report("since expects start or a mark")),
+                                _ => (None, 3),
+                            };
+                            if actions_at >= args.len() {
+                                return Err(ParseError::report("after expects actions"));
+                            }
+                            let events = catch_bail_annotate!(action::parse_leading_event(self, tx.clone(), &args[actions_at..], &mut cmd_iter), "after, parse");
+                            self.after_events.push(AfterTrigger {
+                                seconds,
+                                since,
+                                events,
+                            });
+                        },
+                        "at" => {
+                            let seconds = catch_bail_annotate!(parse_time_of_day(none_bail!(args.get(2), "at expects a time of day")), "at");
+                            if args.len() < 4 {
+                                return Err(ParseError::report("at expects actions"));
+                            }
+                            let events = catch_bail_annotate!(action::parse_leading_event(self, tx.clone(), &args[3..], &mut cmd_iter), "at, parse");
+                            self.clock_events.push(ClockTrigger {
+                                seconds,
+                                events,
+                            });
+                        },
+                        "every" => {
+                            let interval_arg = none_bail!(args.get(2), "every expects an interval");
+                            let interval = catch_bail_annotate!(self.parse_interval(interval_arg), "every");
+                            if interval <= 0.0 {
+                                return Err(ParseError::report("every expects a positive interval"));
+                            }
+                            let mut pipe = None;
+                            // beats line up with the first beat unless a start is given
+                            let mut from = if interval_arg.ends_with('b') { self.parse_time("0b")? } else { 0.0 };
+                            let mut until = None;
+                            let mut actions_at = 3;
+                            loop {
+                                let value = match args.get(actions_at + 1) {
+                                    Some(v) => *v,
+                                    None => break,
+                                };
+                                match args[actions_at] {
+                                    "on" => {
+                                        none_bail!(self.pipes.get(value), format!("No element found: {}", value));
+                                        pipe = Some(value.to_string());
+                                    },
+                                    "from" => from = catch_bail_annotate!(self.parse_time(value), "every from"),
+                                    "until" => until = Some(catch_bail_annotate!(self.parse_time(value), "every until")),
+                                    _ => break,
+                                }
+                                actions_at += 2;
+                            }
+                            if actions_at >= args.len() {
+                                return Err(ParseError::report("every expects actions"));
+                            }
+                            let events = catch_bail_annotate!(action::parse_leading_event(self, tx.clone(), &args[actions_at..], &mut cmd_iter), "every, parse");
+                            self.every_events.push(EveryTrigger {
+                                interval,
+                                pipe,
+                                from,
+                                until,
+                                events,
+                            });
                         },
                         "pre" => {
-                            let events = catch_bail_annotate!(action::parse_leading_event(&mut pattern, tx.clone(), &args[2..], &mut cmd_iter), "pre, parse");
+                            let events = catch_bail_annotate!(action::parse_leading_event(self, tx.clone(), &args[2..], &mut cmd_iter), "pre, parse");
                             for event in events {
-                                pattern.pre_events.push(event)
+                                self.pre_events.push(event)
                             }
                         },
                         "progress" => {
-                            none_bail!(pattern.pipes.get(args[2]), format!("No element found: {}", args[2]));
+                            none_bail!(self.pipes.get(args[2]), format!("No element found: {}", args[2]));
                             let p_name = args[2];
                             let time = catch_bail!(args[3].parse::<f64>(), "Failed to parse time");
                             let nanotime = (time * 1000000000.0) as u64;
-                            let events = catch_bail_annotate!(action::parse_leading_event(&mut pattern, tx.clone(), &args[4..], &mut cmd_iter), "progress, parse");
-                            match pattern.time_events.borrow_mut().get_mut(&(p_name.to_string(), nanotime)) {
-                                Some(v) => {
-                                    for event in events.into_iter() {
-                                        v.push(event)
-                                    }
-                                },
-                                None => {
-                                    pattern.time_events.borrow_mut().insert((p_name.to_string(), nanotime), events);
-                                },
+                            let mut repeat = Repeat::Once;
+                            let mut jump = Jump::Fire;
+                            let mut actions_at = 4;
+                            while let Some(option) = args.get(actions_at) {
+                                match *option {
+                                    "once" => repeat = Repeat::Once,
+                                    "every-pass" => repeat = Repeat::EveryPass,
+                                    "fire" => jump = Jump::Fire,
+                                    "skip" => jump = Jump::Skip,
+                                    _ => break,
+                                }
+                                actions_at += 1;
                             }
+                            if actions_at >= args.len() {
+                                return Err(ParseError::report("progress expects actions"));
+                            }
+                            let events = catch_bail_annotate!(action::parse_leading_event(self, tx.clone(), &args[actions_at..], &mut cmd_iter), "progress, parse");
+                            self.time_events.push(ProgressTrigger {
+                                pipe: p_name.to_string(),
+                                nanos: nanotime,
+                                repeat,
+                                jump,
+                                events,
+                            });
                         }
                         t => return Err(ParseError::report_string(format!("Unknown event type: {}", t)))
                     }
@@ -282,6 +1029,6 @@ impl Pattern {
                 cmd => return Err(ParseError::report_string(format!("Unknown command: {}", cmd))),
             };
         }
-        Ok(pattern)
+        Ok(())
     }
 }